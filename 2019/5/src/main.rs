@@ -1,203 +1,40 @@
-use std::fmt;
-use std::io::Write;
-use std::io::{stdin, stdout};
+use intcode::Cpu;
+use std::collections::VecDeque;
 
 const INPUT_FILE: &str = "input.txt";
 
 fn main() {
-    println!("running part 1");
-    part1();
+    println!("part 1: {}", part1());
+    println!("part 2: {}", part2());
 }
 
-fn part1() {
-    Cpu::new(None).run();
-}
-
-#[derive(Debug, Copy, Clone)]
-enum Parameter {
-    Position(i32),
-    Immediate(i32),
+// Run the diagnostic program for system |id| and collect every output.
+fn diagnostic(id: i64) -> Vec<i64> {
+    Cpu::new(process_input())
+        .with_input(VecDeque::from(vec![id]))
+        .with_output(Vec::new())
+        .run()
+        .unwrap()
+        .output
 }
 
-impl fmt::Display for Parameter {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Parameter::Position(v) => write!(f, "P({})", v),
-            Parameter::Immediate(v) => write!(f, "I({})", v),
-        }
-    }
+fn part1() -> i64 {
+    *diagnostic(1).last().unwrap()
 }
 
-#[derive(Debug)]
-enum Instruction {
-    ADD(Vec<Parameter>),
-    MUL(Vec<Parameter>),
-    INPUT(Vec<Parameter>),
-    OUTPUT(Vec<Parameter>),
-    JUMP(bool, Vec<Parameter>),
-    LESSTHAN(Vec<Parameter>),
-    EQUALS(Vec<Parameter>),
-    HALT,
+fn part2() -> i64 {
+    *diagnostic(5).last().unwrap()
 }
 
-struct Cpu {
-    ip: usize,
-    pub memory: Vec<i32>,
-}
-
-impl Cpu {
-    fn new(mem: Option<Vec<i32>>) -> Cpu {
-        if let Some(memory) = mem {
-            Cpu { ip: 0, memory }
-        } else {
-            Cpu {
-                ip: 0,
-                memory: process_input(),
-            }
-        }
-    }
-
-    // Build a vector of |cnt| parameters for the instruction based on
-    // the flags in the opcode representing the parameter modes.
-    fn pack_parameters(&mut self, cnt: usize) -> Vec<Parameter> {
-        let mut vec = Vec::new();
-        let mut flags = self.memory[self.ip - 1] / 100;
-        for i in 0..cnt {
-            let val = self.memory[self.ip + i] as i32;
-            let param = if flags % 10 == 1 {
-                Parameter::Immediate(val)
-            } else {
-                Parameter::Position(val)
-            };
-            flags /= 10;
-            vec.push(param);
-        }
-        self.ip += cnt;
-        vec
-    }
-
-    fn unpack_parameter(&self, p: Parameter) -> i32 {
-        let v = match p {
-            Parameter::Immediate(x) => x,
-            Parameter::Position(x) => self.memory[x as usize],
-        };
-        v
-    }
-
-    fn fetch_and_decode(&mut self) -> Instruction {
-        self.ip += 1;
-        let opcode = self.memory[self.ip - 1] % 100;
-        match opcode {
-            1 => Instruction::ADD(self.pack_parameters(3)),
-            2 => Instruction::MUL(self.pack_parameters(3)),
-            3 => Instruction::INPUT(self.pack_parameters(1)),
-            4 => Instruction::OUTPUT(self.pack_parameters(1)),
-            5 => Instruction::JUMP(true, self.pack_parameters(2)),
-            6 => Instruction::JUMP(false, self.pack_parameters(2)),
-            7 => Instruction::LESSTHAN(self.pack_parameters(3)),
-            8 => Instruction::EQUALS(self.pack_parameters(3)),
-            99 => Instruction::HALT,
-            _ => panic!("Invalid opcode: {} at position {}", opcode, self.ip - 1),
-        }
-    }
-
-    fn run(mut self) -> Cpu {
-        while self.ip < self.memory.len() {
-            let instruction = self.fetch_and_decode();
-            match instruction {
-                Instruction::ADD(args) => self.op_add(args),
-                Instruction::MUL(args) => self.op_mul(args),
-                Instruction::INPUT(args) => self.op_input(args),
-                Instruction::OUTPUT(args) => self.op_output(args),
-                Instruction::JUMP(test, args) => self.op_jump(test, args),
-                Instruction::LESSTHAN(args) => self.op_lessthan(args),
-                Instruction::EQUALS(args) => self.op_equals(args),
-                Instruction::HALT => break,
-            }
-        }
-        self
-    }
-
-    // Instruction implementations
-    fn op_add(&mut self, args: Vec<Parameter>) {
-        assert_eq!(args.len(), 3);
-        if let Parameter::Position(dest) = args[2] {
-            self.memory[dest as usize] =
-                self.unpack_parameter(args[0]) + self.unpack_parameter(args[1]);
-        } else {
-            panic!("Dest argument should never be immediate");
-        }
-    }
-
-    fn op_mul(&mut self, args: Vec<Parameter>) {
-        assert_eq!(args.len(), 3);
-        if let Parameter::Position(dest) = args[2] {
-            self.memory[dest as usize] =
-                self.unpack_parameter(args[0]) * self.unpack_parameter(args[1]);
-        } else {
-            panic!("Dest argument should never be immediate");
-        }
-    }
-
-    fn op_input(&mut self, args: Vec<Parameter>) {
-        assert_eq!(args.len(), 1);
-
-        if let Parameter::Position(dest) = args[0] {
-            print!("$ ");
-            std::io::stdout().flush().unwrap();
-            let mut buffer = String::new();
-            std::io::stdin().read_line(&mut buffer).unwrap();
-            self.memory[dest as usize] = buffer.trim().parse().unwrap();
-        } else {
-            panic!("Dest argument should never be immediate");
-        }
-    }
-
-    fn op_output(&self, args: Vec<Parameter>) {
-        assert_eq!(args.len(), 1);
-        println!("> {}", self.unpack_parameter(args[0]));
-    }
-
-    fn op_jump(&mut self, test: bool, args: Vec<Parameter>) {
-        assert_eq!(args.len(), 2);
-        if (self.unpack_parameter(args[0]) != 0) == test {
-            self.ip = self.unpack_parameter(args[1]) as usize;
-        }
-    }
-
-    fn op_lessthan(&mut self, args: Vec<Parameter>) {
-        assert_eq!(args.len(), 3);
-        if let Parameter::Position(dest) = args[2] {
-            self.memory[dest as usize] =
-                (self.unpack_parameter(args[0]) < self.unpack_parameter(args[1])) as i32;
-        } else {
-            panic!("Dest argument should never be immediate");
-        }
-    }
-
-    fn op_equals(&mut self, args: Vec<Parameter>) {
-        assert_eq!(args.len(), 3);
-        if let Parameter::Position(dest) = args[2] {
-            self.memory[dest as usize] =
-                (self.unpack_parameter(args[0]) == self.unpack_parameter(args[1])) as i32;
-        } else {
-            panic!("Dest argument should never be immediate");
-        }
-    }
-}
-
-fn process_input() -> Vec<i32> {
-    std::fs::read_to_string(INPUT_FILE)
-        .unwrap()
-        .trim()
-        .split(',')
-        .map(|mass| mass.parse::<i32>().unwrap())
-        .collect()
+fn process_input() -> Vec<i64> {
+    intcode::load(INPUT_FILE)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    const PART1_ANSWER: i64 = 5_074_395;
+    const PART2_ANSWER: i64 = 8_346_937;
 
     #[test]
     fn input_parse() {
@@ -213,12 +50,45 @@ mod tests {
     #[test]
     fn example1() {
         {
-            let cpu = Cpu::new(Some(vec![1101, 100, -1, 4, 0])).run();
+            let cpu = Cpu::new(vec![1101, 100, -1, 4, 0]).run().unwrap();
             assert_eq!(cpu.memory[4], 99);
         }
         {
-            let cpu = Cpu::new(Some(vec![1002, 4, 3, 4, 33])).run();
+            let cpu = Cpu::new(vec![1002, 4, 3, 4, 33]).run().unwrap();
             assert_eq!(cpu.memory[4], 99);
         }
     }
+
+    #[test]
+    fn example2() {
+        // Output 1 if the input is equal to 8 (position mode), else 0
+        let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        for (input, expected) in &[(8, 1), (7, 0)] {
+            let cpu = Cpu::new(program.clone())
+                .with_input(VecDeque::from(vec![*input]))
+                .with_output(Vec::new())
+                .run()
+                .unwrap();
+            assert_eq!(cpu.output, vec![*expected]);
+        }
+    }
+
+    #[test]
+    fn diagnostic_codes() {
+        // Every test passes with a 0 before the final diagnostic code
+        let output = diagnostic(1);
+        let (code, tests) = output.split_last().unwrap();
+        assert!(tests.iter().all(|&t| t == 0));
+        assert_eq!(*code, PART1_ANSWER);
+    }
+
+    #[test]
+    fn part1_regression() {
+        assert_eq!(part1(), PART1_ANSWER);
+    }
+
+    #[test]
+    fn part2_regression() {
+        assert_eq!(part2(), PART2_ANSWER);
+    }
 }
@@ -0,0 +1,265 @@
+//! Chains of amplifiers, each one a `Cpu` whose output feeds the input of
+//! the next machine in line.
+use crate::cpu::{Cpu, Status};
+use crate::error::{ErrorKind, VmError};
+use std::collections::VecDeque;
+use std::fmt;
+
+type Amp = Cpu<VecDeque<i64>, Vec<i64>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainError {
+    /// The chain finished without passing on any signal, or had no
+    /// machines to pass one on.
+    NoSignal,
+    /// One of the machines faulted.
+    Vm(VmError),
+}
+
+impl From<VmError> for ChainError {
+    fn from(e: VmError) -> ChainError {
+        ChainError::Vm(e)
+    }
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainError::NoSignal => write!(f, "no signal"),
+            ChainError::Vm(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChainError {}
+
+pub struct Chain {
+    amps: Vec<Amp>,
+}
+
+impl Chain {
+    // Boot one machine per phase setting, each given its phase as the
+    // first input. An empty chain could never pass on a signal, so
+    // |phases| must not be empty.
+    pub fn new(program: &[i64], phases: &[i64]) -> Result<Chain, ChainError> {
+        if phases.is_empty() {
+            return Err(ChainError::NoSignal);
+        }
+        let amps = phases
+            .iter()
+            .map(|&phase| {
+                Cpu::new(program.to_vec())
+                    .with_input(VecDeque::from(vec![phase]))
+                    .with_output(Vec::new())
+            })
+            .collect();
+        Ok(Chain { amps })
+    }
+
+    // Run each machine to completion in turn, passing every output it
+    // produces on to the next. Returns the last signal out of the chain.
+    pub fn run_serial(mut self, signal: i64) -> Result<i64, ChainError> {
+        let mut signals = vec![signal];
+        for amp in self.amps.iter_mut() {
+            amp.input.extend(signals.drain(..));
+            loop {
+                match amp.resume()? {
+                    Status::Output(v) => signals.push(v),
+                    Status::Halted => break,
+                    Status::NeedsInput => return Err(starved(amp).into()),
+                    Status::Running => unreachable!(),
+                }
+            }
+        }
+        match signals.last() {
+            Some(&signal) => Ok(signal),
+            None => Err(ChainError::NoSignal),
+        }
+    }
+
+    // Loop the output of the last machine back into the first until every
+    // machine has halted. Returns the last signal out of the final machine,
+    // or faults with |InputExhausted| at the first waiting machine if a
+    // whole round passes with none of them producing anything.
+    pub fn run_feedback(mut self, signal: i64) -> Result<i64, ChainError> {
+        let last = self.amps.len() - 1;
+        let mut signal = Some(signal);
+        let mut thrusters = None;
+        loop {
+            let mut progress = false;
+            let mut halted = 0;
+            let mut waiting = None;
+            for (i, amp) in self.amps.iter_mut().enumerate() {
+                if let Some(v) = signal.take() {
+                    amp.push_input(v);
+                }
+                match amp.resume()? {
+                    Status::Output(v) => {
+                        progress = true;
+                        signal = Some(v);
+                        if i == last {
+                            thrusters = Some(v);
+                        }
+                    }
+                    Status::Halted => halted += 1,
+                    Status::NeedsInput => {
+                        waiting.get_or_insert(i);
+                    }
+                    Status::Running => unreachable!(),
+                }
+            }
+            if halted == self.amps.len() {
+                break;
+            }
+            if let (false, Some(i)) = (progress, waiting) {
+                return Err(starved(&mut self.amps[i]).into());
+            }
+        }
+        match thrusters {
+            Some(signal) => Ok(signal),
+            None => Err(ChainError::NoSignal),
+        }
+    }
+}
+
+// Search every ordering of |phases| for the greatest signal out of a serial
+// chain. Returns the signal and the phase settings which produced it.
+pub fn max_serial(program: &[i64], phases: &[i64]) -> Result<(i64, Vec<i64>), ChainError> {
+    let mut best = None;
+    for p in permutations(phases) {
+        let signal = Chain::new(program, &p)?.run_serial(0)?;
+        if best.as_ref().is_none_or(|(b, _)| signal > *b) {
+            best = Some((signal, p));
+        }
+    }
+    best.ok_or(ChainError::NoSignal)
+}
+
+// As |max_serial| but with the chain wired into a feedback loop.
+pub fn max_feedback(program: &[i64], phases: &[i64]) -> Result<(i64, Vec<i64>), ChainError> {
+    let mut best = None;
+    for p in permutations(phases) {
+        let signal = Chain::new(program, &p)?.run_feedback(0)?;
+        if best.as_ref().is_none_or(|(b, _)| signal > *b) {
+            best = Some((signal, p));
+        }
+    }
+    best.ok_or(ChainError::NoSignal)
+}
+
+// The fault for a machine left waiting on an INPUT nothing will feed.
+fn starved(amp: &mut Amp) -> VmError {
+    amp.current = amp.ip;
+    amp.fault(ErrorKind::InputExhausted)
+}
+
+// Every ordering of |items| by Heap's algorithm.
+fn permutations(items: &[i64]) -> Vec<Vec<i64>> {
+    fn generate(k: usize, items: &mut Vec<i64>, out: &mut Vec<Vec<i64>>) {
+        if k <= 1 {
+            out.push(items.clone());
+            return;
+        }
+        for i in 0..k - 1 {
+            generate(k - 1, items, out);
+            if k.is_multiple_of(2) {
+                items.swap(i, k - 1);
+            } else {
+                items.swap(0, k - 1);
+            }
+        }
+        generate(k - 1, items, out);
+    }
+
+    let mut out = Vec::new();
+    generate(items.len(), &mut items.to_vec(), &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permutation_count() {
+        let mut p = permutations(&[0, 1, 2, 3]);
+        assert_eq!(p.len(), 24);
+        p.sort();
+        p.dedup();
+        assert_eq!(p.len(), 24);
+    }
+
+    #[test]
+    fn serial_examples() {
+        let program = [
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        assert_eq!(
+            Chain::new(&program, &[4, 3, 2, 1, 0])
+                .unwrap()
+                .run_serial(0),
+            Ok(43210)
+        );
+        assert_eq!(
+            max_serial(&program, &[0, 1, 2, 3, 4]),
+            Ok((43210, vec![4, 3, 2, 1, 0]))
+        );
+
+        let program = [
+            3, 23, 3, 24, 1002, 24, 10, 24, 1002, 23, -1, 23, 101, 5, 23, 23, 1, 24, 23, 23, 4, 23,
+            99, 0, 0,
+        ];
+        assert_eq!(
+            max_serial(&program, &[0, 1, 2, 3, 4]),
+            Ok((54321, vec![0, 1, 2, 3, 4]))
+        );
+    }
+
+    #[test]
+    fn feedback_examples() {
+        let program = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        assert_eq!(
+            Chain::new(&program, &[9, 8, 7, 6, 5])
+                .unwrap()
+                .run_feedback(0),
+            Ok(139_629_729)
+        );
+        assert_eq!(
+            max_feedback(&program, &[5, 6, 7, 8, 9]),
+            Ok((139_629_729, vec![9, 8, 7, 6, 5]))
+        );
+    }
+
+    #[test]
+    fn faults() {
+        // Reads a second value but never outputs.
+        let program = [3, 0, 3, 0, 99];
+        let err = Chain::new(&program, &[1]).unwrap().run_serial(0);
+        assert_eq!(err, Err(ChainError::NoSignal));
+        let err = max_feedback(&program, &[1]);
+        assert_eq!(err, Err(ChainError::NoSignal));
+
+        // Wants three inputs; the serial chain only ever gives it two.
+        let program = [3, 0, 3, 0, 3, 0, 4, 0, 99];
+        let err = Chain::new(&program, &[1]).unwrap().run_serial(0);
+        assert_eq!(
+            err.map_err(|e| e.to_string()),
+            Err("input exhausted at position 4 (opcode 3)".to_string())
+        );
+
+        // Each machine waits on a second input before it ever outputs.
+        match max_feedback(&program, &[5, 6]) {
+            Err(ChainError::Vm(e)) => assert_eq!((e.ip, e.kind), (4, ErrorKind::InputExhausted)),
+            other => panic!("expected a starved machine, got {:?}", other),
+        }
+
+        assert_eq!(max_serial(&program, &[]), Err(ChainError::NoSignal));
+        assert_eq!(
+            Chain::new(&program, &[]).err().map(|e| e.to_string()),
+            Some("no signal".to_string())
+        );
+    }
+}
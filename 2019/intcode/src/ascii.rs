@@ -0,0 +1,217 @@
+//! Talking to Intcode programs in ASCII.
+//!
+//! Text programs output one character code per word and read their input
+//! as lines of character codes ending in 10. Anything they output beyond
+//! the ASCII range is a numeric result rather than text. `Ascii` drives a
+//! machine a line at a time from code, while `TextInput` and `TextOutput`
+//! plug into any `Cpu` to play one from a terminal.
+use crate::cpu::{Cpu, Status};
+use crate::error::{ErrorKind, VmError};
+use crate::io::{Input, Output};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+/// The words a program reads for |line|, newline included.
+pub fn encode(line: &str) -> Vec<i64> {
+    line.bytes().map(i64::from).chain(Some(10)).collect()
+}
+
+// Whether |word| is a character code rather than a number.
+fn is_ascii(word: i64) -> bool {
+    (0..=127).contains(&word)
+}
+
+/// Everything a program output before it stopped to wait for input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reply {
+    pub text: String,
+    /// Words outside the ASCII range, in the order they were output.
+    pub values: Vec<i64>,
+    pub halted: bool,
+}
+
+pub struct Ascii {
+    pub cpu: Cpu<VecDeque<i64>, Vec<i64>>,
+}
+
+impl Ascii {
+    pub fn new(program: Vec<i64>) -> Ascii {
+        Ascii {
+            cpu: Cpu::new(program)
+                .with_input(VecDeque::new())
+                .with_output(Vec::new()),
+        }
+    }
+
+    pub fn send_line(&mut self, line: &str) {
+        self.cpu.input.extend(encode(line));
+    }
+
+    /// Run until the program wants more input than it has been sent, or
+    /// halts, collecting what it says along the way.
+    pub fn run(&mut self) -> Result<Reply, VmError> {
+        let mut reply = Reply::default();
+        loop {
+            match self.cpu.resume()? {
+                Status::Output(word) if is_ascii(word) => reply.text.push(word as u8 as char),
+                Status::Output(word) => reply.values.push(word),
+                Status::NeedsInput => break,
+                Status::Halted => {
+                    reply.halted = true;
+                    break;
+                }
+                Status::Running => unreachable!(),
+            }
+        }
+        Ok(reply)
+    }
+}
+
+/// Feed the program lines of text read from |R|, one character per word.
+pub struct TextInput<R> {
+    source: R,
+    pending: VecDeque<i64>,
+}
+
+impl<R: BufRead> TextInput<R> {
+    pub fn new(source: R) -> TextInput<R> {
+        TextInput {
+            source,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Input for TextInput<R> {
+    fn read(&mut self) -> Option<i64> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            if self.source.read_line(&mut line).unwrap() == 0 {
+                return None;
+            }
+            self.pending
+                .extend(encode(line.trim_end_matches(['\r', '\n'])));
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Write character codes to |W| as text, and numbers on lines of their own.
+pub struct TextOutput<W> {
+    sink: W,
+}
+
+impl<W: Write> TextOutput<W> {
+    pub fn new(sink: W) -> TextOutput<W> {
+        TextOutput { sink }
+    }
+}
+
+impl<W: Write> Output for TextOutput<W> {
+    fn write(&mut self, word: i64) {
+        if is_ascii(word) {
+            self.sink.write_all(&[word as u8]).unwrap();
+            if word == 10 {
+                self.sink.flush().unwrap();
+            }
+        } else {
+            writeln!(self.sink, "{}", word).unwrap();
+        }
+    }
+}
+
+/// Play the program loaded into |cpu| in the terminal, typing its input and
+/// reading its output as text, until it halts or stdin is closed. Attach a
+/// `transcript::Recorder` to |cpu| first to keep a record of the session.
+pub fn interactive(cpu: Cpu) -> Result<(), VmError> {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    let cpu = cpu
+        .with_input(TextInput::new(stdin.lock()))
+        .with_output(TextOutput::new(stdout.lock()));
+    match cpu.run() {
+        // Closing stdin is how the player quits.
+        Err(VmError {
+            kind: ErrorKind::InputExhausted,
+            ..
+        })
+        | Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // Echo each line back in upper case, until an empty line. Then output
+    // the number of lines read.
+    fn shout() -> Vec<i64> {
+        assemble(
+            "loop:   in [c]
+                    eq [c], #10, [t]
+                    jt [t], #eol
+                    lt [c], #97, [t]
+                    jt [t], #put
+                    add [c], #-32, [c]
+            put:    out [c]
+                    add #0, #1, [text]
+                    jt #1, #loop
+            eol:    out #10
+                    jf [text], #done
+                    add [lines], #1, [lines]
+                    add #0, #0, [text]
+                    jt #1, #loop
+            done:   out [lines]
+                    hlt
+            c:      .data 0
+            t:      .data 0
+            text:   .data 0
+            lines:  .data 1000",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode("NOT A J"), vec![78, 79, 84, 32, 65, 32, 74, 10]);
+        assert_eq!(encode(""), vec![10]);
+    }
+
+    #[test]
+    fn conversation() {
+        let mut ascii = Ascii::new(shout());
+        assert_eq!(ascii.run(), Ok(Reply::default()));
+        ascii.send_line("hello");
+        ascii.send_line("World");
+        assert_eq!(
+            ascii.run(),
+            Ok(Reply {
+                text: "HELLO\nWORLD\n".to_string(),
+                values: vec![],
+                halted: false,
+            })
+        );
+        ascii.send_line("");
+        assert_eq!(
+            ascii.run(),
+            Ok(Reply {
+                text: "\n".to_string(),
+                values: vec![1002],
+                halted: true,
+            })
+        );
+    }
+
+    #[test]
+    fn text_io() {
+        let mut out = Vec::new();
+        Cpu::new(shout())
+            .with_input(TextInput::new(&b"abc\r\nxyz\n\n"[..]))
+            .with_output(TextOutput::new(&mut out))
+            .run()
+            .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "ABC\nXYZ\n\n1002\n");
+    }
+}
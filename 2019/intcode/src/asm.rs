@@ -0,0 +1,314 @@
+//! A two pass assembler producing Intcode program images.
+//!
+//! The syntax matches the disassembler's output:
+//!
+//! ```text
+//! ; count down from 3, printing each value
+//! start:  out [n]
+//!         add [n], #-1, [n]
+//!         jt [n], #start
+//!         hlt
+//! n:      .data 3
+//! ```
+//!
+//! Operands are `[addr]` for position mode, `#value` for immediate mode and
+//! `rb+n` or `rb-n` for relative mode. Addresses and values may be numbers,
+//! labels or a label plus or minus an offset. Lines of a disassembler
+//! listing are accepted as is: the leading address is checked against the
+//! assembler's location and the raw words which follow it are skipped.
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// A value which may not be known until every label has been seen.
+#[derive(Debug, Clone)]
+enum Value {
+    Number(i64),
+    Label(String, i64),
+}
+
+// A single memory word waiting on the second pass to be resolved.
+#[derive(Debug, Clone)]
+struct Word {
+    line: usize,
+    value: Value,
+}
+
+fn opcode(mnemonic: &str) -> Option<(i64, usize)> {
+    Some(match mnemonic {
+        "add" => (1, 3),
+        "mul" => (2, 3),
+        "in" => (3, 1),
+        "out" => (4, 1),
+        "jt" => (5, 2),
+        "jf" => (6, 2),
+        "lt" => (7, 3),
+        "eq" => (8, 3),
+        "arb" => (9, 1),
+        "hlt" => (99, 0),
+        _ => return None,
+    })
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<i64>() {
+        return Ok(Value::Number(n));
+    }
+    let (label, offset) = match s.find(['+', '-']) {
+        Some(i) => {
+            let offset = s[i..]
+                .replace(' ', "")
+                .parse::<i64>()
+                .map_err(|_| format!("invalid offset in `{}`", s))?;
+            (s[..i].trim(), offset)
+        }
+        None => (s, 0),
+    };
+    if !is_identifier(label) || label == "rb" {
+        return Err(format!("invalid value `{}`", s));
+    }
+    Ok(Value::Label(label.to_string(), offset))
+}
+
+// Parse one operand into its parameter mode and value.
+fn parse_operand(s: &str) -> Result<(i64, Value), String> {
+    let s = s.trim();
+    if let Some(inner) = s.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| format!("unterminated operand `{}`", s))?;
+        Ok((0, parse_value(inner)?))
+    } else if let Some(value) = s.strip_prefix('#') {
+        Ok((1, parse_value(value)?))
+    } else if let Some(offset) = s.strip_prefix("rb") {
+        let offset = offset.replace(' ', "");
+        let offset = match offset.as_str() {
+            "" => 0,
+            o if o.starts_with('+') || o.starts_with('-') => o
+                .parse::<i64>()
+                .map_err(|_| format!("invalid relative offset `{}`", s))?,
+            _ => return Err(format!("invalid operand `{}`", s)),
+        };
+        Ok((2, Value::Number(offset)))
+    } else {
+        Err(format!("operand `{}` needs a mode: [p], #i or rb+n", s))
+    }
+}
+
+fn split_operands(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        Vec::new()
+    } else {
+        s.split(',').collect()
+    }
+}
+
+/// Assemble |source| into a program image.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut words: Vec<Word> = Vec::new();
+
+    // First pass lays out every word and records where each label lands.
+    for (i, raw) in source.lines().enumerate() {
+        let line = i + 1;
+        let err = |message: String| AsmError { line, message };
+        let mut text = raw.split(';').next().unwrap().trim();
+
+        // Skip the address and raw words of a disassembler listing.
+        if text.starts_with(|c: char| c.is_ascii_digit()) {
+            let mut tokens = text.splitn(2, char::is_whitespace);
+            let addr = tokens.next().unwrap();
+            if addr.parse::<usize>() != Ok(words.len()) {
+                return Err(err(format!(
+                    "listing address {} doesn't match location {}",
+                    addr,
+                    words.len()
+                )));
+            }
+            text = tokens.next().unwrap_or("").trim_start();
+            while text.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+                text = text
+                    .split_once(char::is_whitespace)
+                    .map_or("", |(_, rest)| rest)
+                    .trim_start();
+            }
+        }
+
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_identifier(label) || label == "rb" {
+                return Err(err(format!("invalid label `{}`", label)));
+            }
+            if labels.insert(label.to_string(), words.len()).is_some() {
+                return Err(err(format!("duplicate label `{}`", label)));
+            }
+            text = text[colon + 1..].trim_start();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        if mnemonic == ".data" {
+            for value in split_operands(rest) {
+                let value = parse_value(value).map_err(err)?;
+                words.push(Word { line, value });
+            }
+            continue;
+        }
+
+        let (op, cnt) =
+            opcode(mnemonic).ok_or_else(|| err(format!("unknown mnemonic `{}`", mnemonic)))?;
+        let operands = split_operands(rest);
+        if operands.len() != cnt {
+            return Err(err(format!(
+                "`{}` takes {} operands, found {}",
+                mnemonic,
+                cnt,
+                operands.len()
+            )));
+        }
+        let mut modes = 0;
+        let mut params = Vec::new();
+        for (n, operand) in operands.iter().enumerate() {
+            let (mode, value) = parse_operand(operand).map_err(err)?;
+            modes += mode * 10i64.pow(n as u32 + 2);
+            params.push(Word { line, value });
+        }
+        words.push(Word {
+            line,
+            value: Value::Number(op + modes),
+        });
+        words.extend(params);
+    }
+
+    // Second pass resolves labels now that every address is known.
+    words
+        .into_iter()
+        .map(|Word { line, value }| match value {
+            Value::Number(n) => Ok(n),
+            Value::Label(label, offset) => labels
+                .get(&label)
+                .map(|&addr| addr as i64 + offset)
+                .ok_or_else(|| AsmError {
+                    line,
+                    message: format!("undefined label `{}`", label),
+                }),
+        })
+        .collect()
+}
+
+/// Render a program image in the comma separated form `parse` reads.
+pub fn format_image(image: &[i64]) -> String {
+    let words: Vec<String> = image.iter().map(|w| w.to_string()).collect();
+    words.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::disasm;
+
+    #[test]
+    fn countdown() {
+        let image = assemble(
+            "; count down from 3, printing each value
+            start:  out [n]
+                    add [n], #-1, [n]
+                    jt [n], #start
+                    hlt
+            n:      .data 3",
+        )
+        .unwrap();
+        assert_eq!(image, vec![4, 10, 1001, 10, -1, 10, 1005, 10, 0, 99, 3]);
+        let cpu = Cpu::new(image).with_output(Vec::new()).run().unwrap();
+        assert_eq!(cpu.output, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn operand_syntax() {
+        let image = assemble(
+            "arb #end+1
+             add rb-1, rb, rb+2
+             mul [3], #-7, [end-1]
+             end: .data 1, -2, end",
+        )
+        .unwrap();
+        assert_eq!(
+            image,
+            vec![109, 11, 22201, -1, 0, 2, 1002, 3, -7, 9, 1, -2, 10]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(assemble("nop").unwrap_err().line, 1);
+        assert_eq!(
+            assemble("hlt\nadd [1], [2]").unwrap_err(),
+            AsmError {
+                line: 2,
+                message: "`add` takes 3 operands, found 2".to_string()
+            }
+        );
+        assert!(assemble("out 5").is_err());
+        assert!(assemble("jt #1, #nowhere").is_err());
+        assert!(assemble("a: hlt\na: hlt").is_err());
+        assert!(assemble("0001  99  hlt").is_err());
+    }
+
+    #[test]
+    fn listing_roundtrip() {
+        let programs = [
+            include_str!("../../5/input.txt"),
+            include_str!("../../9/input.txt"),
+        ];
+        for program in programs.iter() {
+            let image = crate::parse(program);
+            let listing = disasm::listing(&image);
+            assert_eq!(assemble(&listing).unwrap(), image);
+        }
+
+        // Words which only decode by ignoring surplus mode digits
+        let odd = [
+            vec![1199],
+            vec![1104, 5, 99],
+            vec![21199, 0],
+            vec![11109, 3, 99],
+            vec![21104, 7, 1004, 1, 99],
+        ];
+        for image in odd.iter() {
+            let listing = disasm::listing(image);
+            assert_eq!(&assemble(&listing).unwrap(), image, "{}", listing);
+        }
+    }
+
+    #[test]
+    fn image_format() {
+        let image = vec![1101, 100, -1, 4, 0];
+        assert_eq!(crate::parse(&format_image(&image)), image);
+    }
+}
@@ -0,0 +1,47 @@
+// Play an ASCII Intcode program in the terminal. Close stdin to quit.
+// --record keeps a transcript of the session's I/O, which --replay later
+// checks the program still reproduces.
+//
+// usage: ascii <program> [--record <transcript> | --replay <transcript>]
+use intcode::ascii::{self, TextOutput};
+use intcode::transcript::{Recorder, Transcript};
+use intcode::Output;
+use std::fs::File;
+
+const USAGE: &str = "usage: ascii <program> [--record <transcript> | --replay <transcript>]";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (path, mode) = match &args[..] {
+        [path] => (path, None),
+        [path, flag, transcript] => (path, Some((flag.as_str(), transcript))),
+        _ => panic!("{}", USAGE),
+    };
+    let program = intcode::load(path);
+    let result = match mode {
+        None => ascii::interactive(intcode::Cpu::new(program)).map_err(|e| e.to_string()),
+        Some(("--record", transcript)) => {
+            let recorder = Recorder::new(File::create(transcript).unwrap()).unwrap();
+            ascii::interactive(intcode::Cpu::new(program).with_tracer(recorder))
+                .map_err(|e| e.to_string())
+        }
+        Some(("--replay", transcript)) => {
+            let transcript = Transcript::load(transcript).unwrap();
+            transcript
+                .replay(program)
+                .map(|cpu| {
+                    let mut out = TextOutput::new(std::io::stdout());
+                    for &word in cpu.output.iter() {
+                        out.write(word);
+                    }
+                    println!("\nreplayed {} events", transcript.entries.len());
+                })
+                .map_err(|e| e.to_string())
+        }
+        _ => panic!("{}", USAGE),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
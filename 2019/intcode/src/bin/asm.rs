@@ -0,0 +1,14 @@
+// Assemble Intcode source into a comma separated program image.
+//
+// usage: asm <source>
+fn main() {
+    let path = std::env::args().nth(1).expect("usage: asm <source>");
+    let source = std::fs::read_to_string(path).unwrap();
+    match intcode::asm::assemble(&source) {
+        Ok(image) => println!("{}", intcode::asm::format_image(&image)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
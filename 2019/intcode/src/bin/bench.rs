@@ -0,0 +1,99 @@
+// Report throughput in instructions per second on a handful of representative
+// programs, both interpreted and compiled to threaded code. Build with
+// --release for meaningful numbers.
+//
+// usage: bench [seconds per program]
+use intcode::threaded::Program;
+use intcode::Cpu;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+struct Bench {
+    name: &'static str,
+    image: Vec<i64>,
+    input: Vec<i64>,
+}
+
+fn benches() -> Vec<Bench> {
+    let mut gravity = intcode::parse(include_str!("../../../2/input.txt"));
+    gravity[1] = 12;
+    gravity[2] = 2;
+    let countdown = intcode::asm::assemble(
+        "loop:   add [n], #-1, [n]
+                 jt [n], #loop
+                 hlt
+         n:      .data 1000000",
+    )
+    .unwrap();
+    vec![
+        // Tiny, so dominated by setting up each machine
+        Bench {
+            name: "gravity",
+            image: gravity,
+            input: vec![],
+        },
+        Bench {
+            name: "diagnostic",
+            image: intcode::parse(include_str!("../../../5/input.txt")),
+            input: vec![5],
+        },
+        // Deep recursion through the relative base
+        Bench {
+            name: "boost",
+            image: intcode::parse(include_str!("../../../9/input.txt")),
+            input: vec![2],
+        },
+        Bench {
+            name: "countdown",
+            image: countdown,
+            input: vec![],
+        },
+    ]
+}
+
+// Run |bench| once, returning the number of instructions executed. With a
+// |compiled| program it runs as threaded code instead of interpreted.
+fn run(bench: &Bench, compiled: Option<&Program>) -> u64 {
+    let cpu = Cpu::new(bench.image.clone())
+        .with_input(bench.input.iter().copied().collect::<VecDeque<i64>>())
+        .with_output(Vec::new());
+    match compiled {
+        Some(program) => program.run(cpu),
+        None => cpu.run(),
+    }
+    .unwrap()
+    .cycles()
+}
+
+// Instructions per second running |bench| repeatedly for |budget|.
+fn measure(bench: &Bench, compiled: Option<&Program>, budget: Duration) -> (u64, u64, f64) {
+    let (mut runs, mut instructions) = (0u64, 0u64);
+    let start = Instant::now();
+    while runs == 0 || start.elapsed() < budget {
+        instructions += run(bench, compiled);
+        runs += 1;
+    }
+    let rate = instructions as f64 / start.elapsed().as_secs_f64();
+    (runs, instructions, rate)
+}
+
+fn main() {
+    let seconds: f64 = std::env::args()
+        .nth(1)
+        .map_or(1.0, |s| s.parse().expect("usage: bench [seconds]"));
+    let budget = Duration::from_secs_f64(seconds);
+    for bench in benches() {
+        let program = Program::compile(&bench.image);
+        let (runs, instructions, interpreted) = measure(&bench, None, budget);
+        let (_, _, compiled) = measure(&bench, Some(&program), budget);
+        println!(
+            "{:<12} {:>8} runs {:>12} instructions {:>10.2} M/s {:>10.2} M/s compiled ({:.2}x)",
+            bench.name,
+            runs,
+            instructions,
+            interpreted / 1e6,
+            compiled / 1e6,
+            compiled / interpreted
+        );
+    }
+}
@@ -0,0 +1,28 @@
+// Compile a program in the small language of `intcode::compiler` to an
+// Intcode image, or with --asm to the assembly the image is made from.
+//
+// usage: compile <source> [--asm] [--no-optimize]
+use intcode::compiler::Compiler;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let path = args
+        .first()
+        .expect("usage: compile <source> [--asm] [--no-optimize]");
+    let source = std::fs::read_to_string(path).unwrap();
+    let compiler = Compiler::new().with_optimization(!args.iter().any(|a| a == "--no-optimize"));
+    let result = if args.iter().any(|a| a == "--asm") {
+        compiler.assembly(&source)
+    } else {
+        compiler
+            .compile(&source)
+            .map(|image| intcode::asm::format_image(&image) + "\n")
+    };
+    match result {
+        Ok(out) => print!("{}", out),
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// Run an Intcode program once per input set and show which addresses ran as
+// code, which were only used as data and which were never touched, over all
+// the runs together. Each input set is a comma separated list of words.
+//
+// usage: coverage <program> [--list] [input set...]
+use intcode::coverage::{Class, Coverage};
+use intcode::Cpu;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        panic!("usage: coverage <program> [--list] [input set...]");
+    }
+    let image = intcode::load(args.remove(0));
+    let list = args.first().is_some_and(|a| a == "--list");
+    if list {
+        args.remove(0);
+    }
+    // With no input sets, run once on no input
+    let sets: Vec<VecDeque<i64>> = match args.len() {
+        0 => vec![VecDeque::new()],
+        _ => args
+            .iter()
+            .map(|set| intcode::parse(set).into_iter().collect())
+            .collect(),
+    };
+
+    let coverage = Arc::new(Mutex::new(Coverage::new()));
+    for input in sets {
+        let result = Cpu::new(image.clone())
+            .with_input(input.clone())
+            .with_output(Vec::new())
+            .with_tracer(coverage.clone())
+            .run();
+        match result {
+            Ok(cpu) => println!("input {:?}: output {:?}", input, cpu.output),
+            Err(e) => println!("input {:?}: stopped: {}", input, e),
+        }
+    }
+    let coverage = coverage.lock().unwrap();
+    println!();
+    if list {
+        print!("{}", coverage.annotate(&image));
+        return;
+    }
+    print!("{}", coverage.strip(image.len()));
+    println!();
+    for (class, n) in Class::ALL.iter().zip(coverage.counts(image.len()).iter()) {
+        let name = match class {
+            Class::Untouched => "untouched",
+            _ => class.name(),
+        };
+        println!("{}  {:<10}{:>6}", class.symbol(), name, n);
+    }
+}
@@ -0,0 +1,31 @@
+// Debug an Intcode program interactively. An empty line repeats the last
+// command and `quit` or end of input exits. The program reads its input
+// from words queued with the `input` command.
+//
+// usage: debug <program>
+use intcode::debugger::Debugger;
+use intcode::Cpu;
+use std::collections::VecDeque;
+use std::io::{stdin, stdout, Write};
+
+fn main() {
+    let path = std::env::args().nth(1).expect("usage: debug <program>");
+    let cpu = Cpu::new(intcode::load(path)).with_input(VecDeque::new());
+    let mut dbg = Debugger::new(cpu);
+    let mut last = String::from("help");
+    loop {
+        print!("(icdb) ");
+        stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        let line = line.trim();
+        match line {
+            "q" | "quit" => break,
+            "" => {}
+            _ => last = line.to_string(),
+        }
+        println!("{}", dbg.command(&last));
+    }
+}
@@ -0,0 +1,7 @@
+// Decompile an Intcode program to structured pseudo-code.
+//
+// usage: decompile <program>
+fn main() {
+    let path = std::env::args().nth(1).expect("usage: decompile <program>");
+    print!("{}", intcode::decompile::pseudocode(&intcode::load(path)));
+}
@@ -0,0 +1,7 @@
+// Print a listing of an Intcode program image.
+//
+// usage: disasm <program>
+fn main() {
+    let path = std::env::args().nth(1).expect("usage: disasm <program>");
+    print!("{}", intcode::disasm::listing(&intcode::load(path)));
+}
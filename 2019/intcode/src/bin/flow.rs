@@ -0,0 +1,7 @@
+// Print the control-flow graph of an Intcode program as Graphviz source.
+//
+// usage: flow <program> | dot -Tsvg > program.svg
+fn main() {
+    let path = std::env::args().nth(1).expect("usage: flow <program>");
+    print!("{}", intcode::flow::analyze(&intcode::load(path)).to_dot());
+}
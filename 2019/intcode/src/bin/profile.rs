@@ -0,0 +1,31 @@
+// Run an Intcode program and report where it spent its time, followed by
+// a listing annotated with execution counts.
+//
+// usage: profile <program> [input...]
+use intcode::profile::Profile;
+use intcode::Cpu;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().expect("usage: profile <program> [input...]");
+    let input: VecDeque<i64> = args
+        .map(|a| a.parse().expect("input must be numbers"))
+        .collect();
+    let image = intcode::load(path);
+
+    let profile = Arc::new(Mutex::new(Profile::new()));
+    let result = Cpu::new(image.clone())
+        .with_input(input)
+        .with_output(Vec::new())
+        .with_tracer(profile.clone())
+        .run();
+    match result {
+        Ok(cpu) => println!("output {:?}\n", cpu.output),
+        Err(e) => println!("stopped: {}\n", e),
+    }
+    let profile = profile.lock().unwrap();
+    println!("{}", profile.report(20));
+    print!("{}", profile.annotate(&image));
+}
@@ -0,0 +1,1662 @@
+//! A compiler from a small imperative language to Intcode.
+//!
+//! ```text
+//! var count = 5;
+//! var squares[10];
+//!
+//! fn square(n) {
+//!     return n * n;
+//! }
+//!
+//! fn main() {
+//!     var i = 0;
+//!     while (i < count) {
+//!         squares[i] = square(i + read());
+//!         print(squares[i]);
+//!         i = i + 1;
+//!     }
+//! }
+//! ```
+//!
+//! Every value is an integer. Globals are declared outside functions with
+//! an optional constant initialiser, or a size to make them an array, and
+//! locals inside them with any expression, or zero. A local is in scope for
+//! the whole of its function. Expressions have `+`, `-`, `*`, the
+//! comparisons, unary `-` and `!`, calls, array indexing and `read()` for
+//! the next input word; statements are assignments, `if`/`else`, `while`,
+//! `return`, `print(...)` and calls. Execution starts at `main`, which
+//! takes no arguments.
+//!
+//! The output is assembled with `asm`. Calls follow the convention the
+//! decompiler recognises: the caller stores the return address at `rb+0`
+//! and arguments from `rb+1` on, and the function moves the relative base
+//! past its frame with `arb`, leaving its return value in the first
+//! argument's slot.
+//!
+//! Arrays take no room in the image: they are laid out one after another
+//! from its end, with the stack after them, and hold zeros until written.
+//! One may have up to `MAX_ARRAY` elements. An array is indexed by patching
+//! the operand of the instruction doing the load or store. A constant index
+//! outside the array is a compile error, and any other is checked when it
+//! is used: one outside the array jumps to `bounds`, an instruction which
+//! faults with an invalid destination.
+use crate::asm;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+fn error<T>(line: usize, message: String) -> Result<T, CompileError> {
+    Err(CompileError { line, message })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Word(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "`{}`", n),
+            Token::Word(w) => write!(f, "`{}`", w),
+            Token::Symbol(s) => write!(f, "`{}`", s),
+            Token::End => write!(f, "end of input"),
+        }
+    }
+}
+
+// Longest first, so that `<=` isn't taken for `<`.
+const SYMBOLS: [&str; 19] = [
+    "==", "!=", "<=", ">=", "(", ")", "{", "}", "[", "]", ",", ";", "=", "<", ">", "+", "-", "*",
+    "!",
+];
+
+const KEYWORDS: [&str; 8] = [
+    "var", "fn", "if", "else", "while", "return", "print", "read",
+];
+
+fn lex(source: &str) -> Result<Vec<(usize, Token)>, CompileError> {
+    let mut tokens = Vec::new();
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut rest = text.split("//").next().unwrap().trim_start();
+        while !rest.is_empty() {
+            let len = if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                let n = rest[..len]
+                    .parse()
+                    .or_else(|_| error(line, format!("number `{}` is too big", &rest[..len])))?;
+                tokens.push((line, Token::Number(n)));
+                len
+            } else if rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                tokens.push((line, Token::Word(rest[..len].to_string())));
+                len
+            } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+                tokens.push((line, Token::Symbol(symbol)));
+                symbol.len()
+            } else {
+                let c = rest.chars().next().unwrap();
+                return error(line, format!("unexpected character `{}`", c));
+            };
+            rest = rest[len..].trim_start();
+        }
+    }
+    let last = source.lines().count().max(1);
+    tokens.push((last, Token::End));
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Name(String, usize),
+    Index(String, Box<Expr>, usize),
+    Call(String, Vec<Expr>, usize),
+    Read,
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Var(String, Option<Expr>, usize),
+    Assign(Expr, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Print(Expr),
+    Expr(Expr),
+}
+
+struct Global {
+    name: String,
+    // Some(length) for an array.
+    size: Option<i64>,
+    init: i64,
+    line: usize,
+}
+
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    line: usize,
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].0
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].1.clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = match self.peek() {
+            Token::Symbol(s) => *s == symbol,
+            Token::Word(w) => w == symbol,
+            _ => false,
+        };
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), CompileError> {
+        if self.eat(symbol) {
+            return Ok(());
+        }
+        error(
+            self.line(),
+            format!("expected `{}`, found {}", symbol, self.peek()),
+        )
+    }
+
+    fn name(&mut self) -> Result<String, CompileError> {
+        match self.peek() {
+            Token::Word(w) if !KEYWORDS.contains(&w.as_str()) => {
+                let w = w.clone();
+                self.pos += 1;
+                Ok(w)
+            }
+            token => error(self.line(), format!("expected a name, found {}", token)),
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, CompileError> {
+        let negative = self.eat("-");
+        let line = self.line();
+        match self.next() {
+            Token::Number(n) if negative => Ok(-n),
+            Token::Number(n) => Ok(n),
+            token => error(line, format!("expected a number, found {}", token)),
+        }
+    }
+
+    fn program(&mut self) -> Result<(Vec<Global>, Vec<Function>), CompileError> {
+        let mut globals = Vec::new();
+        let mut functions = Vec::new();
+        while *self.peek() != Token::End {
+            if self.eat("var") {
+                loop {
+                    let line = self.line();
+                    let name = self.name()?;
+                    let mut size = None;
+                    let mut init = 0;
+                    if self.eat("[") {
+                        size = Some(self.number()?);
+                        self.expect("]")?;
+                    } else if self.eat("=") {
+                        init = self.number()?;
+                    }
+                    globals.push(Global {
+                        name,
+                        size,
+                        init,
+                        line,
+                    });
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect(";")?;
+            } else if self.eat("fn") {
+                let line = self.line();
+                let name = self.name()?;
+                self.expect("(")?;
+                let mut params = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        params.push(self.name()?);
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                    self.expect(")")?;
+                }
+                let body = self.block()?;
+                functions.push(Function {
+                    name,
+                    params,
+                    body,
+                    line,
+                });
+            } else {
+                return error(
+                    self.line(),
+                    format!("expected `var` or `fn`, found {}", self.peek()),
+                );
+            }
+        }
+        Ok((globals, functions))
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut body = Vec::new();
+        while !self.eat("}") {
+            if *self.peek() == Token::End {
+                return error(self.line(), "expected `}`, found end of input".to_string());
+            }
+            body.extend(self.statement()?);
+        }
+        Ok(body)
+    }
+
+    // A statement, which for `var` may declare several locals.
+    fn statement(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        let stmt = if self.eat("var") {
+            let mut vars = Vec::new();
+            loop {
+                let line = self.line();
+                let name = self.name()?;
+                if *self.peek() == Token::Symbol("[") {
+                    return error(line, format!("array `{}` must be global", name));
+                }
+                let init = match self.eat("=") {
+                    true => Some(self.expr()?),
+                    false => None,
+                };
+                vars.push(Stmt::Var(name, init, line));
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect(";")?;
+            return Ok(vars);
+        } else if self.eat("if") {
+            self.if_else()?
+        } else if self.eat("while") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            Stmt::While(cond, self.block()?)
+        } else if self.eat("return") {
+            let value = match self.eat(";") {
+                true => return Ok(vec![Stmt::Return(None)]),
+                false => self.expr()?,
+            };
+            self.expect(";")?;
+            Stmt::Return(Some(value))
+        } else if self.eat("print") {
+            self.expect("(")?;
+            let value = self.expr()?;
+            self.expect(")")?;
+            self.expect(";")?;
+            Stmt::Print(value)
+        } else {
+            let line = self.line();
+            let target = self.expr()?;
+            let stmt = if self.eat("=") {
+                match target {
+                    Expr::Name(..) | Expr::Index(..) => Stmt::Assign(target, self.expr()?),
+                    _ => return error(line, "can only assign to a variable".to_string()),
+                }
+            } else {
+                Stmt::Expr(target)
+            };
+            self.expect(";")?;
+            stmt
+        };
+        Ok(vec![stmt])
+    }
+
+    // The rest of an `if` statement, after the keyword.
+    fn if_else(&mut self) -> Result<Stmt, CompileError> {
+        self.expect("(")?;
+        let cond = self.expr()?;
+        self.expect(")")?;
+        let then = self.block()?;
+        let otherwise = if !self.eat("else") {
+            vec![]
+        } else if self.eat("if") {
+            vec![self.if_else()?]
+        } else {
+            self.block()?
+        };
+        Ok(Stmt::If(cond, then, otherwise))
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        let left = self.sum()?;
+        let op = match self.peek() {
+            Token::Symbol("<") => BinOp::Lt,
+            Token::Symbol(">") => BinOp::Gt,
+            Token::Symbol("<=") => BinOp::Le,
+            Token::Symbol(">=") => BinOp::Ge,
+            Token::Symbol("==") => BinOp::Eq,
+            Token::Symbol("!=") => BinOp::Ne,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.sum()?;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, CompileError> {
+        let mut left = self.product()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol("+") => BinOp::Add,
+                Token::Symbol("-") => BinOp::Sub,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, CompileError> {
+        let mut left = self.unary()?;
+        while self.eat("*") {
+            left = Expr::Binary(BinOp::Mul, Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.eat("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+        if self.eat("(") {
+            let e = self.expr()?;
+            self.expect(")")?;
+            return Ok(e);
+        }
+        if self.eat("read") {
+            self.expect("(")?;
+            self.expect(")")?;
+            return Ok(Expr::Read);
+        }
+        if let Token::Number(n) = *self.peek() {
+            self.pos += 1;
+            return Ok(Expr::Number(n));
+        }
+        let name = self.name()?;
+        if self.eat("[") {
+            let index = self.expr()?;
+            self.expect("]")?;
+            Ok(Expr::Index(name, Box::new(index), line))
+        } else if self.eat("(") {
+            let mut args = Vec::new();
+            if !self.eat(")") {
+                loop {
+                    args.push(self.expr()?);
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                self.expect(")")?;
+            }
+            Ok(Expr::Call(name, args, line))
+        } else {
+            Ok(Expr::Name(name, line))
+        }
+    }
+}
+
+// A fixed address, possibly relative to a label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Addr {
+    Abs(i64),
+    Label(String, i64),
+}
+
+impl Addr {
+    fn offset(&self, by: i64) -> Addr {
+        match self {
+            Addr::Abs(a) => Addr::Abs(a + by),
+            Addr::Label(label, offset) => Addr::Label(label.clone(), offset + by),
+        }
+    }
+}
+
+impl fmt::Display for Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Addr::Abs(a) => write!(f, "{}", a),
+            Addr::Label(label, 0) => write!(f, "{}", label),
+            Addr::Label(label, offset) => write!(f, "{}{:+}", label, offset),
+        }
+    }
+}
+
+// Where arrays start, just past the end of the image.
+const ARRAYS: &str = "arrays";
+
+// An instruction which faults, for indexes outside an array to go to.
+const BOUNDS: &str = "bounds";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Imm(i64),
+    // An address, as an immediate.
+    Address(Addr),
+    Pos(Addr),
+    // A slot of the frame, counting from the relative base on entry.
+    Slot(i64),
+    // A frame slot holding an intermediate value, read exactly once.
+    Temp(i64),
+    // A slot of the next frame, where calls put their return address and
+    // arguments.
+    Out(i64),
+    // The frame size times the sign given, once it's known.
+    FrameSize(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Label(String),
+    Op(&'static str, Vec<Operand>),
+}
+
+impl Line {
+    // The operands the line reads.
+    fn sources(&self) -> &[Operand] {
+        match self {
+            Line::Op("add" | "mul" | "lt" | "eq", ops) => &ops[..2],
+            Line::Op("in", _) | Line::Label(_) => &[],
+            Line::Op(_, ops) => ops,
+        }
+    }
+
+    fn dest(&self) -> Option<&Operand> {
+        match self {
+            Line::Op("add" | "mul" | "lt" | "eq", ops) => Some(&ops[2]),
+            Line::Op("in", ops) => Some(&ops[0]),
+            _ => None,
+        }
+    }
+
+    fn reads(&self, operand: &Operand) -> bool {
+        self.sources().contains(operand)
+    }
+
+    fn writes(&self, operand: &Operand) -> bool {
+        self.dest() == Some(operand)
+    }
+
+    // Whether the line copies a value, as `add x, #0, d`.
+    fn copy(&self) -> Option<(&Operand, &Operand)> {
+        match self {
+            Line::Op("add", ops) if ops[1] == Operand::Imm(0) => Some((&ops[0], &ops[2])),
+            _ => None,
+        }
+    }
+
+    // Whether control can leave the straight line at this point, or land
+    // in the middle of it.
+    fn barrier(&self) -> bool {
+        matches!(
+            self,
+            Line::Label(_) | Line::Op("jt" | "jf" | "arb" | "hlt", _)
+        )
+    }
+}
+
+// The instruction before |j| computing the temporary |src| it copies to
+// |dst|, if it can compute it there instead.
+fn producer(lines: &[Line], j: usize, src: &Operand, dst: &Operand) -> Option<usize> {
+    if !matches!(src, Operand::Temp(_)) {
+        return None;
+    }
+    for i in (0..j).rev() {
+        let line = &lines[i];
+        if line.writes(src) {
+            return Some(i);
+        }
+        if line.barrier() || line.reads(src) || line.reads(dst) || line.writes(dst) {
+            return None;
+        }
+    }
+    None
+}
+
+// The instruction after |j| using the temporary |dst| it copies |src| to,
+// if it can read |src| instead. A copy patched to load an array element
+// has to stay.
+fn consumer(lines: &[Line], j: usize, src: &Operand, dst: &Operand) -> Option<usize> {
+    if !matches!(dst, Operand::Temp(_)) || matches!(src, Operand::Pos(Addr::Abs(_))) {
+        return None;
+    }
+    for (k, line) in lines.iter().enumerate().skip(j + 1) {
+        if matches!(line, Line::Label(_)) {
+            return None;
+        }
+        if line.reads(dst) {
+            return Some(k);
+        }
+        if line.barrier() || line.writes(src) || line.writes(dst) {
+            return None;
+        }
+    }
+    None
+}
+
+/// Remove loads through temporaries: a value computed into a temporary and
+/// then copied elsewhere is computed there directly, and a value copied
+/// into a temporary is read from where it was by the instruction using it.
+fn optimize(lines: &mut Vec<Line>) {
+    let mut j = 0;
+    while j < lines.len() {
+        let (src, dst) = match lines[j].copy() {
+            Some((src, dst)) => (src.clone(), dst.clone()),
+            None => {
+                j += 1;
+                continue;
+            }
+        };
+        if src == dst {
+            lines.remove(j);
+        } else if let Some(i) = producer(lines, j, &src, &dst) {
+            if let Line::Op(_, ops) = &mut lines[i] {
+                *ops.last_mut().unwrap() = dst;
+            }
+            lines.remove(j);
+            j = i;
+        } else if let Some(k) = consumer(lines, j, &src, &dst) {
+            let n = lines[k].sources().len();
+            if let Line::Op(_, ops) = &mut lines[k] {
+                for op in ops[..n].iter_mut().filter(|op| **op == dst) {
+                    *op = src.clone();
+                }
+            }
+            lines.remove(j);
+        } else {
+            j += 1;
+        }
+    }
+}
+
+/// Most elements an array may have.
+pub const MAX_ARRAY: i64 = 1 << 32;
+
+// Names visible to every function.
+struct Scope<'a> {
+    // Globals, with the offset of their storage from `arrays` and their
+    // size if they're arrays.
+    globals: HashMap<&'a str, Option<(i64, i64)>>,
+    // Functions and how many arguments they take.
+    functions: HashMap<&'a str, usize>,
+}
+
+// Code generation for one function.
+struct Emitter<'a> {
+    scope: &'a Scope<'a>,
+    labels: &'a mut usize,
+    name: &'a str,
+    locals: HashMap<String, i64>,
+    temps: i64,
+    depth: i64,
+    deepest: i64,
+    lines: Vec<Line>,
+}
+
+fn global(name: &str) -> String {
+    format!("g_{}", name)
+}
+
+// The address of |label|, as an immediate.
+fn address(label: String) -> Operand {
+    Operand::Address(Addr::Label(label, 0))
+}
+
+impl<'a> Emitter<'a> {
+    fn label(&mut self) -> String {
+        *self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    fn emit(&mut self, mnemonic: &'static str, ops: Vec<Operand>) {
+        self.lines.push(Line::Op(mnemonic, ops));
+    }
+
+    fn copy(&mut self, from: Operand, to: Operand) {
+        self.emit("add", vec![from, Operand::Imm(0), to]);
+    }
+
+    fn jump(&mut self, to: String) {
+        self.emit("jt", vec![Operand::Imm(1), address(to)]);
+    }
+
+    fn temp(&mut self) -> Operand {
+        let slot = self.temps + self.depth;
+        self.depth += 1;
+        self.deepest = self.deepest.max(self.depth);
+        Operand::Temp(slot)
+    }
+
+    // Temporaries are freed in the reverse of the order they were made.
+    fn free(&mut self, operand: &Operand) {
+        if let Operand::Temp(slot) = operand {
+            self.depth -= 1;
+            debug_assert_eq!(*slot, self.temps + self.depth);
+        }
+    }
+
+    // Where the scalar |name| lives.
+    fn variable(&self, name: &str, line: usize) -> Result<Operand, CompileError> {
+        if let Some(&slot) = self.locals.get(name) {
+            return Ok(Operand::Slot(slot));
+        }
+        match self.scope.globals.get(name) {
+            Some(None) => Ok(Operand::Pos(Addr::Label(global(name), 0))),
+            Some(Some(_)) => error(line, format!("array `{}` needs an index", name)),
+            None => error(line, format!("undefined variable `{}`", name)),
+        }
+    }
+
+    // Where the array |name| starts, and its size.
+    fn array(&self, name: &str, line: usize) -> Result<(Addr, i64), CompileError> {
+        match self.scope.globals.get(name) {
+            _ if self.locals.contains_key(name) => {
+                error(line, format!("`{}` is not an array", name))
+            }
+            Some(&Some((offset, size))) => Ok((Addr::Label(ARRAYS.to_string(), offset), size)),
+            Some(None) => error(line, format!("`{}` is not an array", name)),
+            None => error(line, format!("undefined array `{}`", name)),
+        }
+    }
+
+    // Element |i| of the array |name|, found at |base| by `array`.
+    fn element(
+        &self,
+        name: &str,
+        (base, size): (Addr, i64),
+        i: i64,
+        line: usize,
+    ) -> Result<Operand, CompileError> {
+        if i < 0 || i >= size {
+            return error(
+                line,
+                format!(
+                    "index {} is outside `{}`, which has {} elements",
+                    i, name, size
+                ),
+            );
+        }
+        Ok(Operand::Pos(base.offset(i)))
+    }
+
+    // Point |target|, an operand of an instruction still to come, at the
+    // element |index| of the array at |base|, going to `bounds` if that's
+    // outside the array.
+    fn patch(&mut self, (base, size): (Addr, i64), index: Operand, target: Operand) {
+        let bounds = address(BOUNDS.to_string());
+        self.emit(
+            "add",
+            vec![
+                Operand::Address(base.clone()),
+                index.clone(),
+                target.clone(),
+            ],
+        );
+        self.free(&index);
+        let t = self.temp();
+        self.emit(
+            "lt",
+            vec![target.clone(), Operand::Address(base.clone()), t.clone()],
+        );
+        self.emit("jt", vec![t.clone(), bounds.clone()]);
+        self.emit(
+            "lt",
+            vec![target, Operand::Address(base.offset(size)), t.clone()],
+        );
+        self.emit("jf", vec![t.clone(), bounds]);
+        self.free(&t);
+    }
+
+    // Copy |value| to a temporary if it's in memory, where a call worked
+    // out after it could change it.
+    fn hold(&mut self, value: Operand) -> Operand {
+        match value {
+            Operand::Pos(_) => {
+                let t = self.temp();
+                self.copy(value, t.clone());
+                t
+            }
+            value => value,
+        }
+    }
+
+    fn expr(&mut self, e: &Expr) -> Result<Operand, CompileError> {
+        Ok(match e {
+            Expr::Number(n) => Operand::Imm(*n),
+            Expr::Name(name, line) => self.variable(name, *line)?,
+            Expr::Index(name, index, line) => {
+                let array = self.array(name, *line)?;
+                let index = self.expr(index)?;
+                if let Operand::Imm(i) = index {
+                    return self.element(name, array, i, *line);
+                }
+                // Point the load's source at the element
+                let site = self.label();
+                let source = Operand::Pos(Addr::Label(site.clone(), 1));
+                self.patch(array, index, source);
+                let t = self.temp();
+                self.lines.push(Line::Label(site));
+                self.copy(Operand::Pos(Addr::Abs(0)), t.clone());
+                t
+            }
+            Expr::Call(name, args, line) => {
+                match self.scope.functions.get(name.as_str()) {
+                    Some(&n) if n == args.len() => {}
+                    Some(&n) => {
+                        return error(
+                            *line,
+                            format!("`{}` takes {} arguments, given {}", name, n, args.len()),
+                        )
+                    }
+                    None => return error(*line, format!("undefined function `{}`", name)),
+                }
+                // Arguments are all worked out before any go in place, as
+                // working one out may call something else
+                let mut values = Vec::new();
+                for (n, arg) in args.iter().enumerate() {
+                    let value = self.expr(arg)?;
+                    values.push(match args[n + 1..].iter().any(calls) {
+                        true => self.hold(value),
+                        false => value,
+                    });
+                }
+                for (n, value) in values.iter().enumerate() {
+                    self.copy(value.clone(), Operand::Out(n as i64 + 1));
+                }
+                for value in values.iter().rev() {
+                    self.free(value);
+                }
+                let back = self.label();
+                self.copy(address(back.clone()), Operand::Out(0));
+                self.jump(format!("f_{}", name));
+                self.lines.push(Line::Label(back));
+                let t = self.temp();
+                self.copy(Operand::Out(1), t.clone());
+                t
+            }
+            Expr::Read => {
+                let t = self.temp();
+                self.emit("in", vec![t.clone()]);
+                t
+            }
+            Expr::Neg(inner) => match self.expr(inner)? {
+                Operand::Imm(n) => Operand::Imm(n.wrapping_neg()),
+                value => {
+                    self.free(&value);
+                    let t = self.temp();
+                    self.emit("mul", vec![value, Operand::Imm(-1), t.clone()]);
+                    t
+                }
+            },
+            Expr::Not(inner) => match self.expr(inner)? {
+                Operand::Imm(n) => Operand::Imm((n == 0) as i64),
+                value => {
+                    self.free(&value);
+                    let t = self.temp();
+                    self.emit("eq", vec![value, Operand::Imm(0), t.clone()]);
+                    t
+                }
+            },
+            Expr::Binary(op, a, b) => match self.compare(*op, a, b)? {
+                (value, true) => value,
+                (Operand::Imm(n), false) => Operand::Imm((n == 0) as i64),
+                (value, false) => {
+                    self.free(&value);
+                    let t = self.temp();
+                    self.emit("eq", vec![value, Operand::Imm(0), t.clone()]);
+                    t
+                }
+            },
+        })
+    }
+
+    // The value of |a op b|, or for the comparisons that Intcode doesn't
+    // have, of its opposite with false to say so.
+    fn compare(&mut self, op: BinOp, a: &Expr, b: &Expr) -> Result<(Operand, bool), CompileError> {
+        let mut x = self.expr(a)?;
+        if calls(b) {
+            x = self.hold(x);
+        }
+        let mut y = self.expr(b)?;
+        if op == BinOp::Sub {
+            y = match y {
+                Operand::Imm(n) => Operand::Imm(n.wrapping_neg()),
+                value => {
+                    self.free(&value);
+                    let t = self.temp();
+                    self.emit("mul", vec![value, Operand::Imm(-1), t.clone()]);
+                    t
+                }
+            };
+        }
+        let (mnemonic, swap, truth) = match op {
+            BinOp::Add | BinOp::Sub => ("add", false, true),
+            BinOp::Mul => ("mul", false, true),
+            BinOp::Lt => ("lt", false, true),
+            BinOp::Gt => ("lt", true, true),
+            BinOp::Le => ("lt", true, false),
+            BinOp::Ge => ("lt", false, false),
+            BinOp::Eq => ("eq", false, true),
+            BinOp::Ne => ("eq", false, false),
+        };
+        let (x, y) = if swap { (y, x) } else { (x, y) };
+        if let (Operand::Imm(p), Operand::Imm(q)) = (&x, &y) {
+            let value = match mnemonic {
+                "add" => p.wrapping_add(*q),
+                "mul" => p.wrapping_mul(*q),
+                "lt" => (p < q) as i64,
+                _ => (p == q) as i64,
+            };
+            return Ok((Operand::Imm(value), truth));
+        }
+        // Freed in the reverse of the order the operands were worked out
+        if swap {
+            self.free(&x);
+            self.free(&y);
+        } else {
+            self.free(&y);
+            self.free(&x);
+        }
+        let t = self.temp();
+        self.emit(mnemonic, vec![x, y, t.clone()]);
+        Ok((t, truth))
+    }
+
+    // An operand which is non-zero when |e| is true, or zero when it's true
+    // with false to say so.
+    fn condition(&mut self, e: &Expr) -> Result<(Operand, bool), CompileError> {
+        match e {
+            Expr::Not(inner) => {
+                let (value, truth) = self.condition(inner)?;
+                Ok((value, !truth))
+            }
+            Expr::Binary(op, a, b) => self.compare(*op, a, b),
+            e => Ok((self.expr(e)?, true)),
+        }
+    }
+
+    // Jump to |to| unless |e| is true.
+    fn unless(&mut self, e: &Expr, to: String) -> Result<(), CompileError> {
+        let (value, truth) = self.condition(e)?;
+        self.free(&value);
+        let mnemonic = if truth { "jf" } else { "jt" };
+        self.emit(mnemonic, vec![value, address(to)]);
+        Ok(())
+    }
+
+    fn assign(&mut self, target: &Expr, value: &Expr) -> Result<(), CompileError> {
+        let v = self.expr(value)?;
+        match target {
+            Expr::Name(name, line) => {
+                let dest = self.variable(name, *line)?;
+                self.copy(v.clone(), dest);
+            }
+            Expr::Index(name, index, line) => {
+                let array = self.array(name, *line)?;
+                match self.expr(index)? {
+                    Operand::Imm(i) => {
+                        let dest = self.element(name, array, i, *line)?;
+                        self.copy(v.clone(), dest);
+                    }
+                    index => {
+                        // Point the store's destination at the element
+                        let site = self.label();
+                        let dest = Operand::Pos(Addr::Label(site.clone(), 3));
+                        self.patch(array, index, dest);
+                        self.lines.push(Line::Label(site));
+                        self.copy(v.clone(), Operand::Pos(Addr::Abs(0)));
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+        self.free(&v);
+        Ok(())
+    }
+
+    fn block(&mut self, body: &[Stmt]) -> Result<(), CompileError> {
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) -> Result<(), CompileError> {
+        match stmt {
+            Stmt::Var(name, init, line) => {
+                let target = Expr::Name(name.clone(), *line);
+                let zero = Expr::Number(0);
+                self.assign(&target, init.as_ref().unwrap_or(&zero))?;
+            }
+            Stmt::Assign(target, value) => self.assign(target, value)?,
+            Stmt::If(cond, then, otherwise) => {
+                let skip = self.label();
+                self.unless(cond, skip.clone())?;
+                self.block(then)?;
+                if otherwise.is_empty() {
+                    self.lines.push(Line::Label(skip));
+                } else {
+                    let end = self.label();
+                    self.jump(end.clone());
+                    self.lines.push(Line::Label(skip));
+                    self.block(otherwise)?;
+                    self.lines.push(Line::Label(end));
+                }
+            }
+            Stmt::While(cond, body) => {
+                let top = self.label();
+                let end = self.label();
+                self.lines.push(Line::Label(top.clone()));
+                self.unless(cond, end.clone())?;
+                self.block(body)?;
+                self.jump(top);
+                self.lines.push(Line::Label(end));
+            }
+            Stmt::Return(value) => {
+                if let Some(value) = value {
+                    let v = self.expr(value)?;
+                    self.copy(v.clone(), Operand::Slot(1));
+                    self.free(&v);
+                }
+                self.jump(format!("r_{}", self.name));
+            }
+            Stmt::Print(value) => {
+                let v = self.expr(value)?;
+                self.emit("out", vec![v.clone()]);
+                self.free(&v);
+            }
+            Stmt::Expr(e) => {
+                let v = self.expr(e)?;
+                self.free(&v);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Whether working out |e| calls a function.
+fn calls(e: &Expr) -> bool {
+    match e {
+        Expr::Call(..) => true,
+        Expr::Index(_, e, _) | Expr::Neg(e) | Expr::Not(e) => calls(e),
+        Expr::Binary(_, a, b) => calls(a) || calls(b),
+        Expr::Number(_) | Expr::Name(..) | Expr::Read => false,
+    }
+}
+
+// Every local declared in |body|, in order.
+fn declarations<'b>(body: &'b [Stmt], out: &mut Vec<(&'b str, usize)>) {
+    for stmt in body {
+        match stmt {
+            Stmt::Var(name, _, line) => out.push((name, *line)),
+            Stmt::If(_, then, otherwise) => {
+                declarations(then, out);
+                declarations(otherwise, out);
+            }
+            Stmt::While(_, body) => declarations(body, out),
+            _ => {}
+        }
+    }
+}
+
+fn render(operand: &Operand, frame: i64) -> String {
+    match operand {
+        Operand::Imm(n) => format!("#{}", n),
+        Operand::Address(a) => format!("#{}", a),
+        Operand::Pos(a) => format!("[{}]", a),
+        Operand::Slot(slot) | Operand::Temp(slot) => format!("rb{:+}", slot - frame),
+        Operand::Out(slot) => format!("rb{:+}", slot),
+        Operand::FrameSize(sign) => format!("#{}", sign * frame),
+    }
+}
+
+/// Compiles source to Intcode, optimizing unless told not to.
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    optimize: bool,
+}
+
+impl Default for Compiler {
+    fn default() -> Compiler {
+        Compiler { optimize: true }
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler::default()
+    }
+
+    pub fn with_optimization(mut self, optimize: bool) -> Compiler {
+        self.optimize = optimize;
+        self
+    }
+
+    /// The assembly source for |source|, as `asm` reads it.
+    pub fn assembly(&self, source: &str) -> Result<String, CompileError> {
+        let mut parser = Parser {
+            tokens: lex(source)?,
+            pos: 0,
+        };
+        let (globals, functions) = parser.program()?;
+
+        let mut scope = Scope {
+            globals: HashMap::new(),
+            functions: HashMap::new(),
+        };
+        let mut reserved = 0;
+        for g in globals.iter() {
+            if g.size.is_some_and(|n| !(1..=MAX_ARRAY).contains(&n)) {
+                return error(
+                    g.line,
+                    format!("array `{}` needs a size from 1 to {}", g.name, MAX_ARRAY),
+                );
+            }
+            let array = g.size.map(|n| (reserved, n));
+            if scope.globals.insert(&g.name, array).is_some() {
+                return error(g.line, format!("`{}` is already declared", g.name));
+            }
+            reserved += g.size.unwrap_or(0);
+        }
+        for f in functions.iter() {
+            if scope.functions.insert(&f.name, f.params.len()).is_some() {
+                return error(f.line, format!("function `{}` is already defined", f.name));
+            }
+        }
+        match functions.iter().find(|f| f.name == "main") {
+            Some(f) if !f.params.is_empty() => {
+                return error(f.line, "`main` takes no arguments".to_string())
+            }
+            Some(_) => {}
+            None => return error(parser.line(), "no `main` function".to_string()),
+        }
+
+        let stack = Addr::Label(ARRAYS.to_string(), reserved);
+        let mut out = String::new();
+        writeln!(out, "        arb #{}", stack).unwrap();
+        writeln!(out, "        add #exit, #0, rb+0").unwrap();
+        writeln!(out, "        jt #1, #f_main").unwrap();
+        writeln!(out, "exit:   hlt").unwrap();
+        if reserved > 0 {
+            writeln!(out, "{}: add #0, #0, #0", BOUNDS).unwrap();
+        }
+        let mut labels = 0;
+        for f in functions.iter() {
+            // Slot 0 holds the return address, then come the arguments, the
+            // return value in the first, then locals and temporaries.
+            let mut locals = HashMap::new();
+            let mut names: Vec<(&str, usize)> =
+                f.params.iter().map(|p| (p.as_str(), f.line)).collect();
+            declarations(&f.body, &mut names);
+            for (slot, (name, line)) in names.into_iter().enumerate() {
+                if locals.insert(name.to_string(), slot as i64 + 1).is_some() {
+                    return error(line, format!("`{}` is already declared", name));
+                }
+            }
+            let temps = 1 + (locals.len() as i64).max(1);
+            let mut emitter = Emitter {
+                scope: &scope,
+                labels: &mut labels,
+                name: &f.name,
+                locals,
+                temps,
+                depth: 0,
+                deepest: 0,
+                lines: vec![],
+            };
+            emitter.emit("arb", vec![Operand::FrameSize(1)]);
+            emitter.block(&f.body)?;
+            // Running off the end returns 0
+            emitter.copy(Operand::Imm(0), Operand::Slot(1));
+            emitter.lines.push(Line::Label(format!("r_{}", f.name)));
+            emitter.emit("arb", vec![Operand::FrameSize(-1)]);
+            emitter.emit("jt", vec![Operand::Imm(1), Operand::Out(0)]);
+
+            let frame = temps + emitter.deepest;
+            let mut lines = emitter.lines;
+            if self.optimize {
+                optimize(&mut lines);
+            }
+            writeln!(out, "f_{}:", f.name).unwrap();
+            for line in lines.iter() {
+                match line {
+                    Line::Label(label) => writeln!(out, "{}:", label),
+                    Line::Op(mnemonic, ops) => {
+                        let ops: Vec<String> = ops.iter().map(|op| render(op, frame)).collect();
+                        writeln!(out, "        {} {}", mnemonic, ops.join(", "))
+                    }
+                }
+                .unwrap();
+            }
+        }
+        for g in globals.iter().filter(|g| g.size.is_none()) {
+            writeln!(out, "{}: .data {}", global(&g.name), g.init).unwrap();
+        }
+        writeln!(out, "{}:", ARRAYS).unwrap();
+        Ok(out)
+    }
+
+    /// Compile |source| to a program image.
+    pub fn compile(&self, source: &str) -> Result<Vec<i64>, CompileError> {
+        let assembly = self.assembly(source)?;
+        Ok(asm::assemble(&assembly).expect("compiler produced bad assembly"))
+    }
+}
+
+/// Compile |source| to an optimized program image.
+pub fn compile(source: &str) -> Result<Vec<i64>, CompileError> {
+    Compiler::new().compile(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use std::collections::VecDeque;
+
+    // Name, source, input and the output it should produce.
+    const GOLDEN: [(&str, &str, &[i64], &[i64]); 7] = [
+        (
+            "arithmetic",
+            "fn main() {
+                var a = 7, b = -3;
+                print(a + b * 2);
+                print((a + b) * 2);
+                print(a - b - 1);
+                print(-a * -b);
+                print(a < b);
+                print(a > b);
+                print(a <= 7);
+                print(b >= 0);
+                print(a == 7);
+                print(a != 7);
+                print(!b);
+                print(!!b);
+                print(2 * 3 - 10);
+            }",
+            &[],
+            &[1, 8, 9, -21, 0, 1, 1, 0, 1, 0, 0, 1, -4],
+        ),
+        (
+            "control flow",
+            "// there's no division, so take away until it won't go
+            fn divides(d, n) {
+                while (n > 0) {
+                    n = n - d;
+                }
+                return n == 0;
+            }
+
+            fn main() {
+                var n = 2;
+                while (n < 30) {
+                    var d = 2, prime = 1;
+                    while (d * d <= n) {
+                        if (divides(d, n)) {
+                            prime = 0;
+                        }
+                        d = d + 1;
+                    }
+                    if (prime) {
+                        print(n);
+                    }
+                    n = n + 1;
+                }
+                var x = read();
+                while (x != 0) {
+                    if (x < 0) {
+                        print(-1);
+                    } else if (x < 10) {
+                        print(1);
+                    } else {
+                        print(2);
+                    }
+                    x = read();
+                }
+            }",
+            &[5, -3, 42, 0],
+            &[2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 1, -1, 2],
+        ),
+        (
+            "functions",
+            "var calls;
+
+            fn fib(n) {
+                calls = calls + 1;
+                if (n < 2) {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+
+            fn max(a, b) {
+                if (a > b) {
+                    return a;
+                }
+                return b;
+            }
+
+            fn nothing() {}
+
+            fn main() {
+                print(fib(10));
+                print(calls);
+                print(max(3, max(9, 4)));
+                print(max(fib(5), 4) * 2);
+                print(nothing());
+            }",
+            &[],
+            &[55, 177, 9, 10, 0],
+        ),
+        (
+            "arrays",
+            "var data[10];
+            var n;
+
+            fn swap(i, j) {
+                var t = data[i];
+                data[i] = data[j];
+                data[j] = t;
+            }
+
+            fn main() {
+                n = read();
+                var i = 0;
+                while (i < n) {
+                    data[i] = read();
+                    i = i + 1;
+                }
+                var sorted = 0;
+                while (!sorted) {
+                    sorted = 1;
+                    i = 1;
+                    while (i < n) {
+                        if (data[i - 1] > data[i]) {
+                            swap(i - 1, i);
+                            sorted = 0;
+                        }
+                        i = i + 1;
+                    }
+                }
+                i = 0;
+                while (i < n) {
+                    print(data[i]);
+                    i = i + 1;
+                }
+                data[0] = 100;
+                print(data[0] + data[9]);
+            }",
+            &[5, 4, -2, 9, 0, 7],
+            &[-2, 0, 4, 7, 9, 100],
+        ),
+        (
+            "array edges",
+            "var one[1], a[3], b[3];
+            var huge[4294967296];
+
+            fn main() {
+                one[0] = 5;
+                a[0] = 1;
+                a[2] = 3;
+                b[0] = 7;
+                var i = read();
+                a[i] = read();
+                print(one[0]);
+                print(a[0] + a[1] + a[2]);
+                print(b[0] + b[1] + b[2]);
+                b[a[0]] = 9;
+                print(b[1]);
+                print(a[b[0] - 7]);
+                huge[4294967295] = 11;
+                print(huge[4294967295] + huge[i]);
+                print(huge[0]);
+            }",
+            &[1, 2],
+            &[5, 6, 7, 9, 1, 11, 0],
+        ),
+        (
+            "evaluation order",
+            "var calls;
+
+            fn f() {
+                calls = calls + 1;
+                return 10;
+            }
+
+            fn g(a, b) {
+                return a * 100 + b;
+            }
+
+            fn main() {
+                print(calls + f());
+                print(g(calls, f()));
+                print(f() + calls);
+                print(calls * (f() - calls));
+            }",
+            &[],
+            &[10, 110, 13, 18],
+        ),
+        (
+            "deep recursion",
+            "fn depth(n) {
+                if (n == 0) {
+                    return 0;
+                }
+                return depth(n - 1) + 1;
+            }
+
+            fn main() {
+                print(depth(read()));
+            }",
+            &[50000],
+            &[50000],
+        ),
+    ];
+
+    fn run(image: Vec<i64>, input: &[i64]) -> Cpu<VecDeque<i64>, Vec<i64>> {
+        Cpu::new(image)
+            .with_input(VecDeque::from(input.to_vec()))
+            .with_output(Vec::new())
+            .run()
+            .unwrap()
+    }
+
+    #[test]
+    fn golden() {
+        for &(name, source, input, output) in GOLDEN.iter() {
+            for &optimize in [false, true].iter() {
+                let compiler = Compiler::new().with_optimization(optimize);
+                let image = compiler.compile(source).unwrap();
+                assert_eq!(run(image, input).output, output, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn same_output_either_way() {
+        // Sources where a temporary's value has to survive a label, a call
+        // or an array patch, which the optimizer mustn't reach across
+        let barriers: [(&str, &[i64]); 4] = [
+            (
+                "var x;
+                fn main() {
+                    var t = read() * 2;
+                    while (t > 0) {
+                        x = x + t;
+                        t = t - 3;
+                    }
+                    print(x);
+                }",
+                &[5],
+            ),
+            (
+                "var a[4];
+                fn main() {
+                    var i = read();
+                    a[i] = i * 2 + 1;
+                    a[i + 1] = a[i] * 3;
+                    print(a[i] + a[i + 1]);
+                }",
+                &[1],
+            ),
+            (
+                "var g;
+                fn set(v) {
+                    g = v;
+                    return v;
+                }
+                fn main() {
+                    var t = g + 1;
+                    print(set(5) + t + g);
+                    print(g);
+                }",
+                &[],
+            ),
+            (
+                "fn main() {
+                    var x = read(), y;
+                    if (x) {
+                        y = x * 2;
+                    } else {
+                        y = 7;
+                    }
+                    print(y + 1);
+                }",
+                &[0],
+            ),
+        ];
+        let golden = GOLDEN.iter().map(|&(_, source, input, _)| (source, input));
+        for (source, input) in golden.chain(barriers.iter().copied()) {
+            let plain = Compiler::new().with_optimization(false);
+            let plain = run(plain.compile(source).unwrap(), input);
+            let optimized = run(compile(source).unwrap(), input);
+            assert!(!plain.output.is_empty(), "{}", source);
+            assert_eq!(plain.output, optimized.output, "{}", source);
+        }
+    }
+
+    #[test]
+    fn array_bounds() {
+        let source = "var a[3], b;
+
+            fn main() {
+                b = 4;
+                var i = read();
+                while (i != 9) {
+                    a[i] = read();
+                    print(a[i] + b);
+                    i = read();
+                }
+            }";
+        for &optimize in [false, true].iter() {
+            let compiler = Compiler::new().with_optimization(optimize);
+            let image = compiler.compile(source).unwrap();
+            // Arrays take no room in the image
+            assert!(image.len() < 200);
+            // Where a bad index goes, just after the startup code
+            let bounds = 10;
+            assert_eq!(image[bounds], 11101);
+            for &(input, output, fault) in [
+                (&[2, 5, 9][..], &[9][..], false),
+                (&[3, 5, 9], &[], true),
+                (&[0, 1, -1, 5, 9], &[5], true),
+            ]
+            .iter()
+            {
+                let mut cpu = Cpu::new(image.clone())
+                    .with_input(VecDeque::from(input.to_vec()))
+                    .with_output(Vec::new());
+                let mut seen = Vec::new();
+                let result = loop {
+                    match cpu.resume() {
+                        Ok(crate::Status::Output(v)) => seen.push(v),
+                        Ok(status) => break Ok(status),
+                        Err(e) => break Err((e.ip, e.kind)),
+                    }
+                };
+                assert_eq!(seen, output);
+                match fault {
+                    true => assert_eq!(result, Err((bounds, crate::ErrorKind::InvalidDestination))),
+                    false => assert_eq!(result, Ok(crate::Status::Halted)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn optimization() {
+        // Never worse, and better overall
+        let mut saved = (0, 0);
+        for &(name, source, input, _) in GOLDEN.iter() {
+            let plain = Compiler::new()
+                .with_optimization(false)
+                .compile(source)
+                .unwrap();
+            let optimized = compile(source).unwrap();
+            let size = (plain.len(), optimized.len());
+            let cycles = (run(plain, input).cycles(), run(optimized, input).cycles());
+            assert!(size.1 <= size.0 && cycles.1 <= cycles.0, "{}", name);
+            saved.0 += size.0 - size.1;
+            saved.1 += cycles.0 - cycles.1;
+        }
+        assert!(saved.0 > 0 && saved.1 > 0);
+
+        // A value goes straight where it's needed rather than through a
+        // temporary
+        let assembly = Compiler::new()
+            .assembly("var x; fn main() { x = read() * 2 + 1; print(x); }")
+            .unwrap();
+        let body: Vec<&str> = assembly
+            .lines()
+            .skip_while(|line| *line != "f_main:")
+            .skip(2)
+            .take(3)
+            .map(str::trim)
+            .collect();
+        assert_eq!(
+            body,
+            ["in rb-1", "mul rb-1, #2, rb-1", "add rb-1, #1, [g_x]"]
+        );
+    }
+
+    #[test]
+    fn decompiles() {
+        // The decompiler sees through the calling convention
+        let image = compile(GOLDEN[2].1).unwrap();
+        let text = crate::decompile::pseudocode(&image);
+        let fib = text.find("fn f").map(|i| &text[i..]).unwrap();
+        assert!(fib.contains("(frame[1] - 1);"), "{}", text);
+        assert!(fib.contains("return;"), "{}", text);
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [
+            ("fn main() { print(1) }", "line 1: expected `;`, found `}`"),
+            ("fn main() {\n x = 1;\n}", "line 2: undefined variable `x`"),
+            (
+                "fn f(a) {}\nfn main() { f(1, 2); }",
+                "line 2: `f` takes 1 arguments, given 2",
+            ),
+            (
+                "fn main() { var a[3]; }",
+                "line 1: array `a` must be global",
+            ),
+            (
+                "var a[3];\nfn main() { a = 1; }",
+                "line 2: array `a` needs an index",
+            ),
+            (
+                "var a;\nfn main() { a[0] = 1; }",
+                "line 2: `a` is not an array",
+            ),
+            (
+                "fn main() { var a; var a; }",
+                "line 1: `a` is already declared",
+            ),
+            ("fn f() {}", "line 1: no `main` function"),
+            ("fn main(x) {}", "line 1: `main` takes no arguments"),
+            (
+                "fn main() { 1 = 2; }",
+                "line 1: can only assign to a variable",
+            ),
+            (
+                "var a[9223372036854775807];\nfn main() {}",
+                "line 1: array `a` needs a size from 1 to 4294967296",
+            ),
+            (
+                "var a[3];\nfn main() { a[3] = 1; }",
+                "line 2: index 3 is outside `a`, which has 3 elements",
+            ),
+            (
+                "var a[3];\nfn main() {\n print(a[1 - 2]);\n}",
+                "line 3: index -1 is outside `a`, which has 3 elements",
+            ),
+            (
+                "fn main() { print(1 $ 2); }",
+                "line 1: unexpected character `$`",
+            ),
+        ];
+        for (source, message) in cases.iter() {
+            let err = compile(source).err().unwrap();
+            assert_eq!(err.to_string(), *message);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+// Conformance tests exercising every opcode in every parameter mode.
+//
+// Each generated program first moves the relative base to RB so that
+// position, immediate and relative operands all resolve to different
+// addresses, then runs the instruction under test and reports its result.
+// Every program runs both interpreted and as threaded code.
+use crate::cpu::{Cpu, DecodeError, Status};
+use crate::error::{ErrorKind, VmError};
+use crate::threaded::Program;
+use std::collections::VecDeque;
+
+const RB: i64 = 200;
+// Where position mode operands and destinations live.
+const POS: [i64; 3] = [100, 101, 300];
+// Relative offsets for each operand, resolving to RB + offset.
+const REL: [i64; 3] = [-3, 4, 10];
+
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+const MODES: [Mode; 3] = [Mode::Position, Mode::Immediate, Mode::Relative];
+const WRITABLE: [Mode; 2] = [Mode::Position, Mode::Relative];
+
+impl Mode {
+    fn flag(self) -> i64 {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+
+    // The address an operand in slot |n| refers to in this mode.
+    fn addr(self, n: usize) -> Option<usize> {
+        match self {
+            Mode::Position => Some(POS[n] as usize),
+            Mode::Immediate => None,
+            Mode::Relative => Some((RB + REL[n]) as usize),
+        }
+    }
+}
+
+// Assemble `arb #RB; op a b ...; hlt`, storing operand values wherever
+// their mode says they should be read from. Returns the program and the
+// address the instruction's result lands at, if it writes one.
+fn program(opcode: i64, operands: &[(Mode, i64)], dest: Option<Mode>) -> (Vec<i64>, Option<usize>) {
+    let mut modes: Vec<Mode> = operands.iter().map(|&(m, _)| m).collect();
+    modes.extend(dest);
+    let word = modes
+        .iter()
+        .enumerate()
+        .fold(opcode, |w, (n, m)| w + m.flag() * 10i64.pow(n as u32 + 2));
+
+    let mut image = vec![109, RB, word];
+    let mut cells = Vec::new();
+    for (n, m) in modes.iter().enumerate() {
+        image.push(match m {
+            Mode::Position => POS[n],
+            Mode::Immediate => operands.get(n).map_or(0, |&(_, v)| v),
+            Mode::Relative => REL[n],
+        });
+        if n < operands.len() {
+            if let Some(addr) = m.addr(n) {
+                cells.push((addr, operands[n].1));
+            }
+        }
+    }
+    image.push(99);
+    image.resize(400, 0);
+    for (addr, value) in cells {
+        image[addr] = value;
+    }
+    (image, dest.and_then(|m| m.addr(operands.len())))
+}
+
+type Machine = Cpu<VecDeque<i64>, Vec<i64>>;
+
+// Run |image| both interpreted and compiled to threaded code, checking the
+// two finish in exactly the same state or with the same fault.
+fn both(image: Vec<i64>, input: &[i64]) -> Result<Machine, VmError> {
+    let machine = || {
+        Cpu::new(image.clone())
+            .with_input(input.iter().copied().collect())
+            .with_output(Vec::new())
+    };
+    let interpreted = machine().run();
+    let compiled = Program::compile(&image).run(machine());
+    match (&interpreted, &compiled) {
+        (Ok(a), Ok(b)) => {
+            let words = a.memory.len().max(b.memory.len());
+            assert_eq!(a.memory.slice(0, words), b.memory.slice(0, words));
+            assert_eq!(a.output, b.output);
+            assert_eq!(a.input, b.input);
+            assert_eq!(
+                (a.ip(), a.rbase(), a.cycles(), a.last_write()),
+                (b.ip(), b.rbase(), b.cycles(), b.last_write())
+            );
+        }
+        (a, b) => assert_eq!(a.as_ref().err(), b.as_ref().err()),
+    }
+    interpreted
+}
+
+fn run(image: Vec<i64>, input: &[i64]) -> Machine {
+    both(image, input).unwrap()
+}
+
+fn binary(opcode: i64, f: fn(i64, i64) -> i64) {
+    let pairs = [(6, 7), (7, 6), (-4, -4), (0, 9)];
+    for &a_mode in MODES.iter() {
+        for &b_mode in MODES.iter() {
+            for &dest in WRITABLE.iter() {
+                for &(a, b) in pairs.iter() {
+                    let (image, addr) = program(opcode, &[(a_mode, a), (b_mode, b)], Some(dest));
+                    let cpu = run(image, &[]);
+                    assert_eq!(
+                        cpu.memory[addr.unwrap()],
+                        f(a, b),
+                        "opcode {} modes {:?} {:?} -> {:?} with {} {}",
+                        opcode,
+                        a_mode,
+                        b_mode,
+                        dest,
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn add() {
+    binary(1, |a, b| a + b);
+}
+
+#[test]
+fn mul() {
+    binary(2, |a, b| a * b);
+}
+
+#[test]
+fn lessthan() {
+    binary(7, |a, b| (a < b) as i64);
+}
+
+#[test]
+fn equals() {
+    binary(8, |a, b| (a == b) as i64);
+}
+
+#[test]
+fn input() {
+    for &dest in WRITABLE.iter() {
+        let (image, addr) = program(3, &[], Some(dest));
+        let cpu = run(image, &[-17]);
+        assert_eq!(cpu.memory[addr.unwrap()], -17, "{:?}", dest);
+        assert!(cpu.input.is_empty());
+    }
+}
+
+#[test]
+fn output() {
+    for &mode in MODES.iter() {
+        let (image, _) = program(4, &[(mode, 31)], None);
+        assert_eq!(run(image, &[]).output, vec![31], "{:?}", mode);
+    }
+}
+
+#[test]
+fn jumps() {
+    // Jump to a trailing `out #1` and check whether it ran
+    for &(opcode, test) in [(5, true), (6, false)].iter() {
+        for &cond_mode in MODES.iter() {
+            for &target_mode in MODES.iter() {
+                for &cond in [0, 1, -5].iter() {
+                    let (mut image, _) =
+                        program(opcode, &[(cond_mode, cond), (target_mode, 390)], None);
+                    image[390..393].copy_from_slice(&[104, 1, 99]);
+                    let taken = (cond != 0) == test;
+                    let expected = if taken { vec![1] } else { vec![] };
+                    assert_eq!(
+                        run(image, &[]).output,
+                        expected,
+                        "opcode {} modes {:?} {:?} with {}",
+                        opcode,
+                        cond_mode,
+                        target_mode,
+                        cond
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn relbase() {
+    for &mode in MODES.iter() {
+        let (image, _) = program(9, &[(mode, -50)], None);
+        assert_eq!(run(image, &[]).rbase(), RB - 50, "{:?}", mode);
+    }
+}
+
+#[test]
+fn halt() {
+    let mut cpu = Cpu::new(vec![99, 104, 1]).with_output(Vec::new());
+    assert_eq!(cpu.resume(), Ok(Status::Halted));
+    assert_eq!(cpu.ip(), 0);
+}
+
+#[test]
+fn immediate_destinations() {
+    let cases: [(i64, usize); 5] = [(1, 2), (2, 2), (7, 2), (8, 2), (3, 0)];
+    for &(opcode, n) in cases.iter() {
+        let operands = vec![(Mode::Immediate, 1); n];
+        let (image, _) = program(opcode, &operands, Some(Mode::Immediate));
+        let word = image[2];
+        let err = both(image, &[5]).err().unwrap();
+        assert_eq!(
+            err,
+            VmError {
+                ip: 2,
+                word,
+                kind: ErrorKind::InvalidDestination
+            }
+        );
+    }
+}
+
+#[test]
+fn invalid_modes_and_opcodes() {
+    for &word in [301, 10, 0, 98].iter() {
+        let err = both(vec![word, 0, 0, 0], &[]).err().unwrap();
+        let expected = if word == 301 {
+            DecodeError::InvalidMode(word)
+        } else {
+            DecodeError::InvalidOpcode(word)
+        };
+        assert_eq!(err.kind, ErrorKind::Decode(expected));
+    }
+}
+
+#[test]
+fn quine() {
+    let image = vec![
+        109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+    ];
+    assert_eq!(run(image.clone(), &[]).output, image);
+}
+
+#[test]
+fn boost_self_test() {
+    // BOOST checks every opcode and mode, reporting any which misbehave
+    // ahead of its keycode.
+    let image = crate::parse(include_str!("../../9/input.txt"));
+    assert_eq!(run(image, &[1]).output, vec![3_906_448_201]);
+}
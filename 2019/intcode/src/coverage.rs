@@ -0,0 +1,222 @@
+//! Code and data coverage for the `Cpu`.
+//!
+//! A `Coverage` is a tracer which notes every address executed as the start
+//! of an instruction, every other word fetched as part of one, and every
+//! address loaded or stored as data. Reuse one across several runs, or
+//! `merge` separate ones, to see what a set of inputs exercised between
+//! them: in a program like the day 5 diagnostic this picks out the self
+//! test code from the payload, and both from words never touched at all.
+use crate::cpu::Instruction;
+use crate::disasm::Line;
+use crate::trace::{Record, Tracer};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// How an address was used, from most to least code-like. An address used
+/// in several ways takes the first that applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Class {
+    /// The first word of an executed instruction.
+    Executed,
+    /// A parameter word of an executed instruction.
+    Operand,
+    /// Stored to as data, and perhaps loaded too.
+    Written,
+    /// Only ever loaded as data.
+    Read,
+    Untouched,
+}
+
+impl Class {
+    pub const ALL: [Class; 5] = [
+        Class::Executed,
+        Class::Operand,
+        Class::Written,
+        Class::Read,
+        Class::Untouched,
+    ];
+
+    /// The character standing for the class in a heat strip.
+    pub fn symbol(self) -> char {
+        match self {
+            Class::Executed => '#',
+            Class::Operand => '+',
+            Class::Written => 'w',
+            Class::Read => 'r',
+            Class::Untouched => '.',
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Executed => "exec",
+            Class::Operand => "operand",
+            Class::Written => "write",
+            Class::Read => "read",
+            Class::Untouched => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub heads: BTreeSet<usize>,
+    pub operands: BTreeSet<usize>,
+    pub reads: BTreeSet<usize>,
+    pub writes: BTreeSet<usize>,
+}
+
+impl Tracer for Coverage {
+    fn record(&mut self, record: &Record) {
+        self.heads.insert(record.ip);
+        self.operands
+            .extend(record.ip + 1..record.ip + record.instruction.width());
+        self.reads.extend(record.loads.iter().copied());
+        self.writes.extend(record.write.map(|(addr, _)| addr));
+    }
+}
+
+// Addresses per row of a heat strip.
+const STRIP_WIDTH: usize = 64;
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage::default()
+    }
+
+    /// Add in everything |other| saw.
+    pub fn merge(&mut self, other: &Coverage) {
+        self.heads.extend(other.heads.iter().copied());
+        self.operands.extend(other.operands.iter().copied());
+        self.reads.extend(other.reads.iter().copied());
+        self.writes.extend(other.writes.iter().copied());
+    }
+
+    pub fn class(&self, addr: usize) -> Class {
+        if self.heads.contains(&addr) {
+            Class::Executed
+        } else if self.operands.contains(&addr) {
+            Class::Operand
+        } else if self.writes.contains(&addr) {
+            Class::Written
+        } else if self.reads.contains(&addr) {
+            Class::Read
+        } else {
+            Class::Untouched
+        }
+    }
+
+    /// Number of addresses below |len| in each class, in `Class::ALL` order.
+    pub fn counts(&self, len: usize) -> [usize; 5] {
+        let mut counts = [0; 5];
+        for addr in 0..len {
+            counts[self.class(addr) as usize] += 1;
+        }
+        counts
+    }
+
+    /// The classes of addresses below |len| as rows of one character per
+    /// address, each row headed by the address it starts at.
+    pub fn strip(&self, len: usize) -> String {
+        let mut out = String::new();
+        for row in (0..len).step_by(STRIP_WIDTH) {
+            let cells: String = (row..len.min(row + STRIP_WIDTH))
+                .map(|addr| self.class(addr).symbol())
+                .collect();
+            writeln!(out, "{:04}  {}", row, cells).unwrap();
+        }
+        out
+    }
+
+    /// A listing of |image| with each line's class in the margin. Executed
+    /// instructions are decoded where they actually started, and anything
+    /// else the way a static disassembly would, short of running into the
+    /// start of an executed instruction.
+    pub fn annotate(&self, image: &[i64]) -> String {
+        let mut out = String::new();
+        let mut addr = 0;
+        while addr < image.len() {
+            let instruction = Instruction::decode(image, addr).ok().filter(|i| {
+                self.heads.contains(&addr)
+                    || !(addr + 1..addr + i.width()).any(|a| self.heads.contains(&a))
+            });
+            let width = instruction.map_or(1, |i| i.width());
+            let line = Line {
+                addr,
+                words: image[addr..addr + width].to_vec(),
+                instruction,
+            };
+            writeln!(out, "{:<8}{}", self.class(addr).name(), line).unwrap();
+            addr += width;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    fn cover(image: &[i64], input: Vec<i64>, coverage: &Arc<Mutex<Coverage>>) {
+        Cpu::new(image.to_vec())
+            .with_input(VecDeque::from(input))
+            .with_output(Vec::new())
+            .with_tracer(coverage.clone())
+            .run()
+            .unwrap();
+    }
+
+    #[test]
+    fn classes() {
+        // 0: in [10]  2: jf [10], #7  5: out [11]  7: hlt  8: out #1
+        let image = vec![3, 10, 1006, 10, 7, 4, 11, 99, 104, 1, 0, 5];
+        let coverage = Arc::new(Mutex::new(Coverage::new()));
+        cover(&image, vec![0], &coverage);
+        let once = coverage.lock().unwrap().clone();
+        assert_eq!(once.strip(12), "0000  #+#++..#..w.\n");
+        assert_eq!(once.counts(12), [3, 3, 1, 0, 5]);
+
+        // Taking the other branch reads [11] as well
+        cover(&image, vec![1], &coverage);
+        let both = coverage.lock().unwrap().clone();
+        assert_eq!(both.strip(12), "0000  #+#++#+#..wr\n");
+        assert_eq!(both.class(8), Class::Untouched);
+
+        let mut merged = Coverage::new();
+        merged.merge(&once);
+        let second = Arc::new(Mutex::new(Coverage::new()));
+        cover(&image, vec![1], &second);
+        merged.merge(&second.lock().unwrap());
+        assert_eq!(merged, both);
+
+        let listing = both.annotate(&image);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], format!("exec    {:<34}in [10]", "0000  3 10"));
+        assert_eq!(lines[4], format!("{:<8}{:<34}out #1", "", "0008  104 1"));
+        assert_eq!(lines[6], format!("read    {:<34}.data 5", "0011  5"));
+    }
+
+    #[test]
+    fn diagnostic() {
+        let image = crate::parse(include_str!("../../5/input.txt"));
+        let mut runs = Vec::new();
+        for &input in [1, 5].iter() {
+            let coverage = Arc::new(Mutex::new(Coverage::new()));
+            cover(&image, vec![input], &coverage);
+            runs.push(coverage.lock().unwrap().clone());
+        }
+        // Each system ID exercises code the other doesn't
+        assert!(!runs[0].heads.is_subset(&runs[1].heads));
+        assert!(!runs[1].heads.is_subset(&runs[0].heads));
+        let mut all = runs[0].clone();
+        all.merge(&runs[1]);
+        assert_eq!(all.heads.len(), runs[0].heads.union(&runs[1].heads).count());
+        assert_eq!(
+            all.strip(image.len()).lines().count(),
+            image.len().div_ceil(64)
+        );
+    }
+}
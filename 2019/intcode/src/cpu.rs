@@ -0,0 +1,993 @@
+use crate::error::{ErrorKind, VmError};
+use crate::io::{Input, Output, Queue, Stdin, Stdout};
+use crate::memory::{Memory, Words};
+use crate::snapshot::Snapshot;
+use crate::trace::{Record, Tracer};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Arc;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Parameter {
+    Position(i64),
+    Immediate(i64),
+    Relative(i64),
+}
+
+impl fmt::Debug for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Parameter::Position(v) => write!(f, "P({})", v),
+            Parameter::Immediate(v) => write!(f, "I({})", v),
+            Parameter::Relative(v) => write!(f, "R({})", v),
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    ADD([Parameter; 3]),
+    MUL([Parameter; 3]),
+    INPUT([Parameter; 1]),
+    OUTPUT([Parameter; 1]),
+    JUMP(bool, [Parameter; 2]),
+    LESSTHAN([Parameter; 3]),
+    EQUALS([Parameter; 3]),
+    RELBASE([Parameter; 1]),
+    HALT,
+}
+
+/// Why a word could not be decoded as an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidOpcode(i64),
+    InvalidMode(i64),
+    /// The instruction's parameters run past the end of memory.
+    Truncated,
+}
+
+impl Instruction {
+    pub fn decode<M: Words + ?Sized>(memory: &M, ip: usize) -> Result<Instruction, DecodeError> {
+        let word = memory.word(ip).ok_or(DecodeError::Truncated)?;
+        // Unpack the |i|th parameter of the instruction, using the flag for
+        // it in the opcode to pick its mode.
+        let param = |i: usize| -> Result<Parameter, DecodeError> {
+            let val = memory.word(ip + 1 + i).ok_or(DecodeError::Truncated)?;
+            Ok(match word / [100, 1000, 10000][i] % 10 {
+                0 => Parameter::Position(val),
+                1 => Parameter::Immediate(val),
+                2 => Parameter::Relative(val),
+                _ => return Err(DecodeError::InvalidMode(word)),
+            })
+        };
+
+        Ok(match word % 100 {
+            1 => Instruction::ADD([param(0)?, param(1)?, param(2)?]),
+            2 => Instruction::MUL([param(0)?, param(1)?, param(2)?]),
+            3 => Instruction::INPUT([param(0)?]),
+            4 => Instruction::OUTPUT([param(0)?]),
+            5 => Instruction::JUMP(true, [param(0)?, param(1)?]),
+            6 => Instruction::JUMP(false, [param(0)?, param(1)?]),
+            7 => Instruction::LESSTHAN([param(0)?, param(1)?, param(2)?]),
+            8 => Instruction::EQUALS([param(0)?, param(1)?, param(2)?]),
+            9 => Instruction::RELBASE([param(0)?]),
+            99 => Instruction::HALT,
+            _ => return Err(DecodeError::InvalidOpcode(word)),
+        })
+    }
+
+    pub fn parameters(&self) -> &[Parameter] {
+        match self {
+            Instruction::ADD(args)
+            | Instruction::MUL(args)
+            | Instruction::LESSTHAN(args)
+            | Instruction::EQUALS(args) => args,
+            Instruction::INPUT(args) | Instruction::OUTPUT(args) | Instruction::RELBASE(args) => {
+                args
+            }
+            Instruction::JUMP(_, args) => args,
+            Instruction::HALT => &[],
+        }
+    }
+
+    // The parameters the instruction reads values from, which is all of
+    // them bar the destination of a writing instruction.
+    pub fn sources(&self) -> &[Parameter] {
+        match self {
+            Instruction::ADD(args)
+            | Instruction::MUL(args)
+            | Instruction::LESSTHAN(args)
+            | Instruction::EQUALS(args) => &args[..2],
+            Instruction::INPUT(_) => &[],
+            _ => self.parameters(),
+        }
+    }
+
+    // Number of memory words the instruction occupies, opcode included.
+    pub fn width(&self) -> usize {
+        1 + self.parameters().len()
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::ADD(_) => "add",
+            Instruction::MUL(_) => "mul",
+            Instruction::INPUT(_) => "in",
+            Instruction::OUTPUT(_) => "out",
+            Instruction::JUMP(true, _) => "jt",
+            Instruction::JUMP(false, _) => "jf",
+            Instruction::LESSTHAN(_) => "lt",
+            Instruction::EQUALS(_) => "eq",
+            Instruction::RELBASE(_) => "arb",
+            Instruction::HALT => "hlt",
+        }
+    }
+}
+
+/// What a `Cpu` reports back to its caller after `step` or `resume`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// An instruction executed and there is nothing to report.
+    Running,
+    /// An `INPUT` found its source empty. The instruction will retry once
+    /// the machine is resumed.
+    NeedsInput,
+    /// An `OUTPUT` produced a value.
+    Output(i64),
+    /// The machine reached `HALT`. Resuming it will halt again.
+    Halted,
+}
+
+/// How `ADD` and `MUL` treat results too big for a 64 bit word. Each
+/// behaves the same in debug and release builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arithmetic {
+    /// Wrap around in two's complement.
+    #[default]
+    Wrapping,
+    /// Fault at the offending instruction.
+    Checked,
+    /// Keep results in 128 bits. A value too big for a word only faults
+    /// once it is output or used as an address, jump target or base.
+    Widened,
+}
+
+pub struct Cpu<I = Stdin, O = Stdout> {
+    pub(crate) ip: usize,
+    // Address of the instruction being executed, for reporting faults
+    // after the ip has moved past it.
+    pub(crate) current: usize,
+    pub(crate) rbase: i64,
+    pub(crate) last_write: Option<usize>,
+    // Instructions executed so far.
+    pub(crate) cycles: u64,
+    tracer: Option<Box<dyn Tracer + Send>>,
+    pub(crate) arithmetic: Arithmetic,
+    // Instructions the machine may execute before it faults.
+    pub(crate) budget: Option<u64>,
+    pub(crate) detect_loops: bool,
+    pub memory: Memory,
+    pub input: I,
+    pub output: O,
+}
+
+impl Cpu {
+    // A new Cpu talks to the terminal until it is given other I/O.
+    pub fn new(memory: Vec<i64>) -> Cpu {
+        Cpu {
+            ip: 0,
+            current: 0,
+            rbase: 0,
+            last_write: None,
+            cycles: 0,
+            tracer: None,
+            arithmetic: Arithmetic::default(),
+            budget: None,
+            detect_loops: false,
+            memory: Memory::from(memory),
+            input: Stdin,
+            output: Stdout,
+        }
+    }
+}
+
+// Forking a machine copies everything but its tracer.
+impl<I: Clone, O: Clone> Clone for Cpu<I, O> {
+    fn clone(&self) -> Self {
+        Cpu {
+            ip: self.ip,
+            current: self.current,
+            rbase: self.rbase,
+            last_write: self.last_write,
+            cycles: self.cycles,
+            tracer: None,
+            arithmetic: self.arithmetic,
+            budget: self.budget,
+            detect_loops: self.detect_loops,
+            memory: self.memory.clone(),
+            input: self.input.clone(),
+            output: self.output.clone(),
+        }
+    }
+}
+
+impl<I: Input + Queue, O: Output + Queue> Cpu<I, O> {
+    /// Capture the machine's complete state, including input it has yet to
+    /// read and output still waiting to be collected.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            ip: self.ip,
+            rbase: self.rbase,
+            cycles: self.cycles,
+            arithmetic: self.arithmetic,
+            budget: self.budget,
+            detect_loops: self.detect_loops,
+            memory: Arc::new(self.memory.clone()),
+            input: self.input.pending(),
+            output: self.output.pending(),
+        }
+    }
+
+    /// Rebuild a machine exactly as it was when |snapshot| was taken.
+    pub fn restore(snapshot: &Snapshot) -> Self {
+        Cpu {
+            ip: snapshot.ip,
+            current: snapshot.ip,
+            rbase: snapshot.rbase,
+            last_write: None,
+            cycles: snapshot.cycles,
+            tracer: None,
+            arithmetic: snapshot.arithmetic,
+            budget: snapshot.budget,
+            detect_loops: snapshot.detect_loops,
+            memory: (*snapshot.memory).clone(),
+            input: I::refill(&snapshot.input),
+            output: O::refill(&snapshot.output),
+        }
+    }
+}
+
+impl<O: Output> Cpu<VecDeque<i64>, O> {
+    pub fn push_input(&mut self, value: i64) {
+        self.input.push_back(value);
+    }
+}
+
+// Hash of a single memory cell. Zero cells hash to zero, so they don't
+// matter to `fingerprint` whether they are stored or not.
+fn mix(addr: usize, value: i128) -> u64 {
+    if value == 0 {
+        return 0;
+    }
+    let mut x = (addr as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (value as u64)
+        ^ ((value >> 64) as u64).rotate_left(32);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Hash of everything in |memory|: the sum of each cell's hash, so that a
+// write only has to swap out the old value's hash for the new one.
+fn fingerprint(memory: &Memory) -> u64 {
+    let mut hash = 0u64;
+    for (start, words) in memory.chunks() {
+        for (i, &word) in words.iter().enumerate() {
+            hash = hash.wrapping_add(mix(start + i, word as i128));
+        }
+    }
+    for (addr, value) in memory.wide_cells() {
+        hash = hash
+            .wrapping_sub(mix(addr, memory.get(addr) as i128))
+            .wrapping_add(mix(addr, value));
+    }
+    hash
+}
+
+impl<I: Input, O: Output> Cpu<I, O> {
+    pub fn with_input<J: Input>(self, input: J) -> Cpu<J, O> {
+        Cpu {
+            ip: self.ip,
+            current: self.current,
+            rbase: self.rbase,
+            last_write: self.last_write,
+            cycles: self.cycles,
+            tracer: self.tracer,
+            arithmetic: self.arithmetic,
+            budget: self.budget,
+            detect_loops: self.detect_loops,
+            memory: self.memory,
+            input,
+            output: self.output,
+        }
+    }
+
+    pub fn with_output<P: Output>(self, output: P) -> Cpu<I, P> {
+        Cpu {
+            ip: self.ip,
+            current: self.current,
+            rbase: self.rbase,
+            last_write: self.last_write,
+            cycles: self.cycles,
+            tracer: self.tracer,
+            arithmetic: self.arithmetic,
+            budget: self.budget,
+            detect_loops: self.detect_loops,
+            memory: self.memory,
+            input: self.input,
+            output,
+        }
+    }
+
+    /// Report every instruction executed from now on to |tracer|.
+    pub fn with_tracer<T: Tracer + Send + 'static>(mut self, tracer: T) -> Self {
+        self.tracer = Some(Box::new(tracer));
+        self
+    }
+
+    pub fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    /// Fault with `CycleLimit` rather than execute more than |cycles|
+    /// instructions in total. The machine can carry on once given a bigger
+    /// budget.
+    pub fn with_cycle_limit(mut self, cycles: u64) -> Self {
+        self.budget = Some(cycles);
+        self
+    }
+
+    pub fn set_cycle_limit(&mut self, cycles: Option<u64>) {
+        self.budget = cycles;
+    }
+
+    /// Have `resume` fault with `InfiniteLoop` if the machine returns to a
+    /// state it was already in, with the same ip, relative base and memory,
+    /// without doing any I/O in between. The fault is reported at the
+    /// loop's entry, once the machine has been round it a second time to
+    /// confirm it. Every state passed through since the machine last read
+    /// input is remembered by hash, so this makes execution a good deal
+    /// slower and costs memory in proportion to the cycles run between
+    /// inputs. A cycle limit bounds both.
+    pub fn with_loop_detection(mut self) -> Self {
+        self.detect_loops = true;
+        self
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    /// Fault any access at or beyond |limit| words of memory.
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.memory.set_limit(Some(limit));
+        self
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn rbase(&self) -> i64 {
+        self.rbase
+    }
+
+    // The address written by the most recent step, if any.
+    pub fn last_write(&self) -> Option<usize> {
+        self.last_write
+    }
+
+    // Number of instructions executed. An INPUT waiting for a value or a
+    // HALT doesn't count until it completes, which HALT never does.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub(crate) fn fault(&self, kind: ErrorKind) -> VmError {
+        VmError {
+            ip: self.current,
+            word: self.memory.get(self.current),
+            kind,
+        }
+    }
+
+    // Check a computed address, faulting on ones the program can't use.
+    fn address(&self, addr: i64) -> Result<usize, VmError> {
+        self.memory
+            .address(addr)
+            .map_err(|e| self.fault(ErrorKind::Memory(e)))
+    }
+
+    // The address |x| words from the relative base.
+    fn relative(&self, x: i64) -> Result<usize, VmError> {
+        match self.rbase.checked_add(x) {
+            Some(addr) => self.address(addr),
+            None => Err(self.fault(ErrorKind::Overflow)),
+        }
+    }
+
+    // The value of an operand, which has to fit in a word.
+    fn unpack_parameter(&self, p: Parameter) -> Result<i64, VmError> {
+        let addr = match p {
+            Parameter::Immediate(x) => return Ok(x),
+            Parameter::Position(x) => self.address(x)?,
+            Parameter::Relative(x) => self.relative(x)?,
+        };
+        if self.memory.is_wide(addr) {
+            return Err(self.fault(ErrorKind::Overflow));
+        }
+        Ok(self.memory.get(addr))
+    }
+
+    // The value of an operand in full, even if it doesn't fit in a word.
+    fn unpack_wide(&self, p: Parameter) -> Result<i128, VmError> {
+        Ok(match p {
+            Parameter::Immediate(x) => x as i128,
+            Parameter::Position(x) => self.memory.get_wide(self.address(x)?),
+            Parameter::Relative(x) => self.memory.get_wide(self.relative(x)?),
+        })
+    }
+
+    fn fetch_and_decode(&mut self) -> Result<Instruction, VmError> {
+        match Instruction::decode(&self.memory, self.ip) {
+            Ok(instruction) => {
+                self.ip += instruction.width();
+                Ok(instruction)
+            }
+            Err(e) => Err(self.fault(ErrorKind::Decode(e))),
+        }
+    }
+
+    // Execute a single instruction. The ip is left on an INPUT that had
+    // nothing to read, or on a HALT, so that they repeat when resumed. On
+    // a fault the ip is left on the faulting instruction.
+    pub fn step(&mut self) -> Result<Status, VmError> {
+        let start = self.ip;
+        self.current = start;
+        self.last_write = None;
+        // Operand values have to be captured before the instruction runs
+        // and perhaps overwrites them.
+        let before = match self.tracer {
+            Some(_) => self.operands(start),
+            None => None,
+        };
+        let rbase = self.rbase;
+        let status = self.execute();
+        match status {
+            Ok(Status::NeedsInput) | Ok(Status::Halted) | Err(_) => self.ip = start,
+            _ => {}
+        }
+        if let (Some(mut record), Ok(status)) = (before, &status) {
+            if *status != Status::NeedsInput {
+                record.write = self.last_write.map(|addr| (addr, self.memory.get(addr)));
+                record.rbase = Some(self.rbase).filter(|&r| r != rbase);
+                self.tracer.as_mut().unwrap().record(&record);
+            }
+        }
+        if let Ok(Status::Running) | Ok(Status::Output(_)) = status {
+            self.cycles += 1;
+        }
+        status
+    }
+
+    // Start a trace record for the instruction at |ip|, with the values of
+    // its source operands and the addresses they were loaded from. An
+    // instruction that can't be decoded is left to fault when executed. An
+    // operand that can't be read as a word is recorded as `None`, since
+    // under widened arithmetic the instruction may still run.
+    fn operands(&self, ip: usize) -> Option<Record> {
+        let instruction = Instruction::decode(&self.memory, ip).ok()?;
+        let mut reads = Vec::new();
+        let mut loads = Vec::new();
+        for &p in instruction.sources() {
+            reads.push(self.unpack_parameter(p).ok());
+            let load = match p {
+                Parameter::Position(x) => self.address(x).ok(),
+                Parameter::Relative(x) => self.relative(x).ok(),
+                Parameter::Immediate(_) => None,
+            };
+            loads.extend(load);
+        }
+        Some(Record {
+            cycle: self.cycles,
+            ip,
+            word: self.memory.get(ip),
+            instruction,
+            reads,
+            loads,
+            write: None,
+            rbase: None,
+        })
+    }
+
+    fn execute(&mut self) -> Result<Status, VmError> {
+        self.address(self.ip as i64)?;
+        let instruction = self.fetch_and_decode()?;
+        // HALT doesn't count as a cycle, so is never over budget.
+        if self.budget.is_some_and(|b| self.cycles >= b) && instruction != Instruction::HALT {
+            return Err(self.fault(ErrorKind::CycleLimit));
+        }
+        match instruction {
+            Instruction::ADD(args) => self.op_add(args)?,
+            Instruction::MUL(args) => self.op_mul(args)?,
+            Instruction::INPUT(args) => {
+                if !self.op_input(args)? {
+                    return Ok(Status::NeedsInput);
+                }
+            }
+            Instruction::OUTPUT(args) => return Ok(Status::Output(self.op_output(args)?)),
+            Instruction::JUMP(test, args) => self.op_jump(test, args)?,
+            Instruction::LESSTHAN(args) => self.op_lessthan(args)?,
+            Instruction::EQUALS(args) => self.op_equals(args)?,
+            Instruction::RELBASE(args) => self.op_relbase(args)?,
+            Instruction::HALT => return Ok(Status::Halted),
+        }
+        Ok(Status::Running)
+    }
+
+    // Step until the machine has something to report.
+    pub fn resume(&mut self) -> Result<Status, VmError> {
+        if self.detect_loops {
+            return self.resume_watching();
+        }
+        loop {
+            match self.step()? {
+                Status::Running => continue,
+                status => return Ok(status),
+            }
+        }
+    }
+
+    // Resume, remembering every state the machine passes through until it
+    // next does I/O and faulting if one comes round again. The memory hash
+    // is kept up to date from the one word each instruction can write.
+    //
+    // A hash match alone could be a collision, so it only makes a
+    // candidate: the machine's memory is copied, and if it really is in a
+    // loop it will be back in exactly that state as many cycles later as
+    // it took to come round the first time. Only then does it fault.
+    fn resume_watching(&mut self) -> Result<Status, VmError> {
+        let mut hash = fingerprint(&self.memory);
+        // The cycle each state was last seen in.
+        let mut seen = HashMap::new();
+        // The cycle a candidate state is due to come round again, and what
+        // it looked like.
+        let mut candidate: Option<(u64, usize, i64, Memory)> = None;
+        loop {
+            if let Some((due, ip, rbase, memory)) = &candidate {
+                if self.cycles == *due {
+                    if (self.ip, self.rbase) == (*ip, *rbase) && self.memory.same_contents(memory) {
+                        self.current = self.ip;
+                        return Err(self.fault(ErrorKind::InfiniteLoop));
+                    }
+                    candidate = None;
+                }
+            }
+            if let Some(then) = seen.insert((self.ip, self.rbase, hash), self.cycles) {
+                if candidate.is_none() {
+                    let due = self.cycles + (self.cycles - then);
+                    candidate = Some((due, self.ip, self.rbase, self.memory.clone()));
+                }
+            }
+            let instruction = Instruction::decode(&self.memory, self.ip).ok();
+            let target = match instruction {
+                Some(Instruction::ADD([.., dest]))
+                | Some(Instruction::MUL([.., dest]))
+                | Some(Instruction::LESSTHAN([.., dest]))
+                | Some(Instruction::EQUALS([.., dest]))
+                | Some(Instruction::INPUT([dest])) => self.destination(dest).ok(),
+                _ => None,
+            };
+            let old = target.map(|addr| self.memory.get_wide(addr));
+            match self.step()? {
+                Status::Running => {}
+                status => return Ok(status),
+            }
+            match (self.last_write, target.zip(old)) {
+                (Some(addr), Some((t, old))) if addr == t => {
+                    hash = hash
+                        .wrapping_sub(mix(addr, old))
+                        .wrapping_add(mix(addr, self.memory.get_wide(addr)))
+                }
+                (Some(_), _) => hash = fingerprint(&self.memory),
+                (None, _) => {}
+            }
+            if let Some(Instruction::INPUT(_)) = instruction {
+                seen.clear();
+                candidate = None;
+            }
+        }
+    }
+
+    // Run to completion, writing outputs to the attached sink.
+    pub fn run(mut self) -> Result<Self, VmError> {
+        loop {
+            match self.resume()? {
+                Status::Output(value) => self.output.write(value),
+                Status::Halted => break,
+                Status::NeedsInput => {
+                    self.current = self.ip;
+                    return Err(self.fault(ErrorKind::InputExhausted));
+                }
+                Status::Running => unreachable!(),
+            }
+        }
+        Ok(self)
+    }
+
+    // Resolve the address a writing instruction stores its result to.
+    fn destination(&self, dest: Parameter) -> Result<usize, VmError> {
+        match dest {
+            Parameter::Position(x) => self.address(x),
+            Parameter::Relative(x) => self.relative(x),
+            Parameter::Immediate(_) => Err(self.fault(ErrorKind::InvalidDestination)),
+        }
+    }
+
+    fn store(&mut self, addr: usize, value: i64) -> Result<(), VmError> {
+        self.memory
+            .set(addr, value)
+            .map_err(|e| self.fault(ErrorKind::Memory(e)))?;
+        self.last_write = Some(addr);
+        Ok(())
+    }
+
+    fn write(&mut self, dest: Parameter, value: i64) -> Result<(), VmError> {
+        self.store(self.destination(dest)?, value)
+    }
+
+    // Combine the first two operands by the |checked|, |wrapping| or
+    // |widened| form of an operation, as the arithmetic policy says, and
+    // store the result to the third.
+    fn arithmetic(
+        &mut self,
+        args: [Parameter; 3],
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+        widened: fn(i128, i128) -> Option<i128>,
+    ) -> Result<(), VmError> {
+        if self.arithmetic == Arithmetic::Widened {
+            let (a, b) = (self.unpack_wide(args[0])?, self.unpack_wide(args[1])?);
+            let value = widened(a, b).ok_or_else(|| self.fault(ErrorKind::Overflow))?;
+            let addr = self.destination(args[2])?;
+            self.memory
+                .set_wide(addr, value)
+                .map_err(|e| self.fault(ErrorKind::Memory(e)))?;
+            self.last_write = Some(addr);
+            return Ok(());
+        }
+        let (a, b) = (
+            self.unpack_parameter(args[0])?,
+            self.unpack_parameter(args[1])?,
+        );
+        let value = match self.arithmetic {
+            Arithmetic::Checked => checked(a, b).ok_or_else(|| self.fault(ErrorKind::Overflow))?,
+            _ => wrapping(a, b),
+        };
+        self.write(args[2], value)
+    }
+
+    // Instruction implementations
+    fn op_add(&mut self, args: [Parameter; 3]) -> Result<(), VmError> {
+        self.arithmetic(args, i64::checked_add, i64::wrapping_add, i128::checked_add)
+    }
+
+    fn op_mul(&mut self, args: [Parameter; 3]) -> Result<(), VmError> {
+        self.arithmetic(args, i64::checked_mul, i64::wrapping_mul, i128::checked_mul)
+    }
+
+    // Returns false without side effects if there was no input to read.
+    fn op_input(&mut self, args: [Parameter; 1]) -> Result<bool, VmError> {
+        // Resolve the destination first so a fault doesn't swallow input.
+        let addr = self.destination(args[0])?;
+        match self.input.read() {
+            Some(value) => self.store(addr, value)?,
+            None => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn op_output(&self, args: [Parameter; 1]) -> Result<i64, VmError> {
+        self.unpack_parameter(args[0])
+    }
+
+    fn op_jump(&mut self, test: bool, args: [Parameter; 2]) -> Result<(), VmError> {
+        if (self.unpack_parameter(args[0])? != 0) == test {
+            self.ip = self.address(self.unpack_parameter(args[1])?)?;
+        }
+        Ok(())
+    }
+
+    fn op_lessthan(&mut self, args: [Parameter; 3]) -> Result<(), VmError> {
+        let value = (self.unpack_wide(args[0])? < self.unpack_wide(args[1])?) as i64;
+        self.write(args[2], value)
+    }
+
+    fn op_equals(&mut self, args: [Parameter; 3]) -> Result<(), VmError> {
+        let value = (self.unpack_wide(args[0])? == self.unpack_wide(args[1])?) as i64;
+        self.write(args[2], value)
+    }
+
+    // A relative base which overflows is never meaningful, whatever the
+    // arithmetic policy.
+    fn op_relbase(&mut self, args: [Parameter; 1]) -> Result<(), VmError> {
+        let offset = self.unpack_parameter(args[0])?;
+        self.rbase = self
+            .rbase
+            .checked_add(offset)
+            .ok_or_else(|| self.fault(ErrorKind::Overflow))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryError;
+    use crate::testing::doubler;
+
+    #[test]
+    fn add_mul() {
+        let cpu = Cpu::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50])
+            .run()
+            .unwrap();
+        assert_eq!(cpu.memory[0], 3500);
+        assert_eq!(cpu.memory[3], 70);
+    }
+
+    #[test]
+    fn parameter_modes() {
+        let cpu = Cpu::new(vec![1002, 4, 3, 4, 33]).run().unwrap();
+        assert_eq!(cpu.memory[4], 99);
+        let cpu = Cpu::new(vec![1101, 100, -1, 4, 0]).run().unwrap();
+        assert_eq!(cpu.memory[4], 99);
+    }
+
+    #[test]
+    fn relative_base() {
+        // Move the base to 10 then store [rb-1] + #5 into [20]
+        let cpu = Cpu::new(vec![109, 10, 1201, -1, 5, 20, 99, 0, 0, 37])
+            .run()
+            .unwrap();
+        assert_eq!(cpu.rbase(), 10);
+        assert_eq!(cpu.memory[20], 42);
+    }
+
+    #[test]
+    fn jumps_and_compares() {
+        // [9] = (#3 < #8), jump to 99 at 12 if it's set, else clobber [9]
+        let cpu = Cpu::new(vec![1107, 3, 8, 9, 1005, 9, 12, 99, 0, 0, 0, 0, 99])
+            .run()
+            .unwrap();
+        assert_eq!(cpu.memory[9], 1);
+        let cpu = Cpu::new(vec![1108, 3, 8, 9, 1006, 9, 12, 99, 0, 0, 0, 0, 99])
+            .run()
+            .unwrap();
+        assert_eq!(cpu.memory[9], 0);
+        assert_eq!(cpu.ip(), 12);
+    }
+
+    #[test]
+    fn queued_io() {
+        // Echo two inputs back in reverse order
+        let cpu = Cpu::new(vec![3, 11, 3, 12, 4, 12, 4, 11, 99])
+            .with_input(VecDeque::from(vec![7, 8]))
+            .with_output(Vec::new())
+            .run()
+            .unwrap();
+        assert!(cpu.input.is_empty());
+        assert_eq!(cpu.output, vec![8, 7]);
+    }
+
+    #[test]
+    fn resume_on_input() {
+        let mut cpu = Cpu::new(doubler()).with_input(VecDeque::new());
+        assert_eq!(cpu.resume(), Ok(Status::NeedsInput));
+        assert_eq!(cpu.ip(), 0);
+        assert_eq!(cpu.resume(), Ok(Status::NeedsInput));
+        cpu.push_input(21);
+        assert_eq!(cpu.resume(), Ok(Status::Output(42)));
+        assert_eq!(cpu.resume(), Ok(Status::NeedsInput));
+        cpu.push_input(5);
+        cpu.push_input(0);
+        assert_eq!(cpu.resume(), Ok(Status::Output(10)));
+        assert_eq!(cpu.resume(), Ok(Status::Halted));
+        assert_eq!(cpu.resume(), Ok(Status::Halted));
+    }
+
+    #[test]
+    fn memory_grows() {
+        // [5000] = #6 * #7, then read it back out
+        let cpu = Cpu::new(vec![1102, 6, 7, 5000, 4, 5000, 4, 6000, 99])
+            .with_output(Vec::new())
+            .run()
+            .unwrap();
+        assert_eq!(cpu.memory[5000], 42);
+        assert_eq!(cpu.output, vec![42, 0]);
+    }
+
+    #[test]
+    fn faults() {
+        let err = Cpu::new(vec![109, -2, 204, 1, 99]).run().err().unwrap();
+        assert_eq!(err.ip, 2);
+        assert_eq!(err.word, 204);
+        assert_eq!(err.kind, ErrorKind::Memory(MemoryError::Negative(-1)));
+        assert_eq!(
+            err.to_string(),
+            "negative address -1 at position 2 (opcode 204)"
+        );
+
+        let err = Cpu::new(vec![1102, 6, 7, 5000, 99])
+            .with_memory_limit(4096)
+            .run()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::Memory(MemoryError::OutOfRange(5000)));
+
+        let err = Cpu::new(vec![1101, 1, 1, 0, 42]).run().err().unwrap();
+        assert_eq!(
+            (err.ip, err.word, err.kind),
+            (4, 42, ErrorKind::Decode(DecodeError::InvalidOpcode(42)))
+        );
+
+        let err = Cpu::new(vec![301, 1, 1, 0, 99]).run().err().unwrap();
+        assert_eq!(err.kind, ErrorKind::Decode(DecodeError::InvalidMode(301)));
+
+        let err = Cpu::new(vec![11101, 1, 1, 0, 99]).run().err().unwrap();
+        assert_eq!(err.kind, ErrorKind::InvalidDestination);
+
+        let err = Cpu::new(vec![3, 0, 99])
+            .with_input(VecDeque::new())
+            .run()
+            .err()
+            .unwrap();
+        assert_eq!((err.ip, err.kind), (0, ErrorKind::InputExhausted));
+    }
+
+    #[test]
+    fn arithmetic_policies() {
+        // The day 9 examples fit in a word under every policy
+        for &policy in [
+            Arithmetic::Wrapping,
+            Arithmetic::Checked,
+            Arithmetic::Widened,
+        ]
+        .iter()
+        {
+            for (image, expected) in [
+                (
+                    vec![1102, 34915192, 34915192, 7, 4, 7, 99, 0],
+                    1_219_070_632_396_864,
+                ),
+                (vec![104, 1125899906842624, 99], 1_125_899_906_842_624),
+            ] {
+                let cpu = Cpu::new(image)
+                    .with_output(Vec::new())
+                    .with_arithmetic(policy)
+                    .run()
+                    .unwrap();
+                assert_eq!(cpu.output, vec![expected], "{:?}", policy);
+            }
+        }
+
+        // [20] = #2^62 * #4, [21] = #0 < [20], out [21], out [20]
+        let big = 1 << 62;
+        let image = vec![1102, big, 4, 20, 107, 0, 20, 21, 4, 21, 4, 20, 99];
+        let cpu = Cpu::new(image.clone())
+            .with_output(Vec::new())
+            .run()
+            .unwrap();
+        assert_eq!(cpu.output, vec![0, 0]);
+
+        let err = Cpu::new(image.clone())
+            .with_arithmetic(Arithmetic::Checked)
+            .run()
+            .err()
+            .unwrap();
+        assert_eq!((err.ip, err.kind), (0, ErrorKind::Overflow));
+
+        let mut cpu = Cpu::new(image)
+            .with_output(Vec::new())
+            .with_arithmetic(Arithmetic::Widened);
+        assert_eq!(cpu.memory.get_wide(20), 0);
+        assert_eq!(cpu.resume(), Ok(Status::Output(1)));
+        assert_eq!(cpu.memory.get_wide(20), 1 << 64);
+        let err = cpu.resume().err().unwrap();
+        assert_eq!((err.ip, err.kind), (10, ErrorKind::Overflow));
+        assert_eq!(
+            err.to_string(),
+            "arithmetic overflow at position 10 (opcode 4)"
+        );
+
+        // Neither can the relative base
+        let err = Cpu::new(vec![109, i64::MAX, 109, 1, 99])
+            .run()
+            .err()
+            .unwrap();
+        assert_eq!((err.ip, err.kind), (2, ErrorKind::Overflow));
+    }
+
+    #[test]
+    fn cycle_limit() {
+        // Count [8] down from 3: six instructions, then hlt
+        let countdown = vec![1001, 8, -1, 8, 1005, 8, 0, 99, 3];
+        let cpu = Cpu::new(countdown.clone())
+            .with_cycle_limit(6)
+            .run()
+            .unwrap();
+        assert_eq!(cpu.cycles(), 6);
+
+        let mut cpu = Cpu::new(countdown).with_cycle_limit(5);
+        let err = cpu.resume().err().unwrap();
+        assert_eq!((err.ip, err.kind), (4, ErrorKind::CycleLimit));
+        assert_eq!(cpu.cycles(), 5);
+        cpu.set_cycle_limit(None);
+        assert_eq!(cpu.resume(), Ok(Status::Halted));
+
+        let err = Cpu::new(vec![1105, 1, 0])
+            .with_cycle_limit(1000)
+            .run()
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "cycle limit reached at position 0 (opcode 1105)"
+        );
+    }
+
+    #[test]
+    fn loop_detection() {
+        let err = Cpu::new(vec![1105, 1, 0])
+            .with_loop_detection()
+            .run()
+            .err()
+            .unwrap();
+        assert_eq!((err.ip, err.kind), (0, ErrorKind::InfiniteLoop));
+
+        // add #1, #0, [10] runs once, then jt #1, #4 spins on its own
+        let mut cpu = Cpu::new(vec![1101, 1, 0, 10, 1105, 1, 4, 99]).with_loop_detection();
+        let err = cpu.resume().err().unwrap();
+        assert_eq!((err.ip, err.kind), (4, ErrorKind::InfiniteLoop));
+        assert_eq!(err.to_string(), "infinite loop at position 4 (opcode 1105)");
+        // Once round the loop to spot it and once more to confirm it
+        assert_eq!(cpu.cycles(), 3);
+        assert_eq!(cpu.memory[10], 1);
+
+        // A loop which changes memory every time round isn't one
+        let cpu = Cpu::new(vec![1001, 8, -1, 8, 1005, 8, 0, 99, 3])
+            .with_loop_detection()
+            .run()
+            .unwrap();
+        assert_eq!(cpu.memory[8], 0);
+
+        // Nor is one which reads input, even if it reads the same word
+        let err = Cpu::new(vec![3, 10, 1105, 1, 0])
+            .with_input(VecDeque::from(vec![1, 1, 1]))
+            .with_loop_detection()
+            .run()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind, ErrorKind::InputExhausted);
+
+        // Nor a long computation with the relative base moving about
+        let image = crate::parse(include_str!("../../9/input.txt"));
+        let cpu = Cpu::new(image)
+            .with_input(VecDeque::from(vec![1]))
+            .with_output(Vec::new())
+            .with_loop_detection()
+            .run()
+            .unwrap();
+        assert_eq!(cpu.output, vec![3_906_448_201]);
+    }
+
+    #[test]
+    fn fault_leaves_ip() {
+        let mut cpu = Cpu::new(vec![1101, 1, 1, 0, 42]);
+        assert_eq!(cpu.step(), Ok(Status::Running));
+        assert!(cpu.step().is_err());
+        assert_eq!(cpu.ip(), 4);
+        assert!(cpu.step().is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! An interactive debugger wrapped around a `Cpu`.
+//!
+//! Breakpoints stop execution before the instruction at an address runs and
+//! watchpoints stop it after any instruction writes to an address. The
+//! `command` method implements a small gdb flavoured command language so
+//! that front ends only need to shuttle lines back and forth. Input for the
+//! program is queued with the `input` command rather than read from
+//! wherever the commands come from.
+use crate::cpu::{Cpu, Instruction, Status};
+use crate::error::VmError;
+use crate::io::{Input, Output, Stdin, Stdout};
+use std::collections::BTreeSet;
+use std::fmt;
+
+// Most instructions `list` will show, and words `dump` will print, for one
+// command. Anything past that is cut off so a stray argument can't hang
+// the front end.
+const MAX_LIST: usize = 1000;
+const MAX_DUMP: usize = 8000;
+
+const HELP: &str = "\
+break <addr>       stop before executing the instruction at addr (alias b)
+delete <addr>      remove a breakpoint (alias d)
+watch <addr>       stop after addr is written (alias w)
+unwatch <addr>     remove a watchpoint
+step [n]           execute n instructions, default 1 (alias s)
+continue           run until a breakpoint, watchpoint or halt (alias c)
+regs               print the registers and the next instruction (alias r)
+dump <from> [to]   print memory from..to, default 8, at most 8000 words (alias x)
+list [n]           disassemble n instructions from ip, default 5, at most 1000 (alias l)
+info               list breakpoints and watchpoints (alias i)
+input <word>...    queue words for the program to read";
+
+/// Why execution returned control to the debugger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// The requested number of instructions ran.
+    Stepped,
+    Breakpoint(usize),
+    /// An instruction wrote |value| to the watched |addr|.
+    Watchpoint(usize, i64),
+    NeedsInput,
+    Halted,
+    Error(VmError),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Stepped => write!(f, "stepped"),
+            Stop::Breakpoint(addr) => write!(f, "breakpoint at {}", addr),
+            Stop::Watchpoint(addr, value) => write!(f, "watchpoint: [{}] = {}", addr, value),
+            Stop::NeedsInput => write!(f, "waiting for input"),
+            Stop::Halted => write!(f, "halted"),
+            Stop::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+pub struct Debugger<I = Stdin, O = Stdout> {
+    pub cpu: Cpu<I, O>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+}
+
+impl<I: Input, O: Output> Debugger<I, O> {
+    pub fn new(cpu: Cpu<I, O>) -> Debugger<I, O> {
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn add_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.insert(addr)
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: usize) -> bool {
+        self.watchpoints.remove(&addr)
+    }
+
+    // Execute a single instruction, returning a reason to stop if there
+    // is one. Output is passed through to the Cpu's sink as it appears.
+    fn single(&mut self) -> Option<Stop> {
+        match self.cpu.step() {
+            Ok(Status::Running) => {}
+            Ok(Status::Output(v)) => self.cpu.output.write(v),
+            Ok(Status::NeedsInput) => return Some(Stop::NeedsInput),
+            Ok(Status::Halted) => return Some(Stop::Halted),
+            Err(e) => return Some(Stop::Error(e)),
+        }
+        match self.cpu.last_write() {
+            Some(addr) if self.watchpoints.contains(&addr) => {
+                Some(Stop::Watchpoint(addr, self.cpu.memory[addr]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Execute up to |n| instructions, stopping early for breakpoints,
+    /// watchpoints or anything else the Cpu reports.
+    pub fn step(&mut self, n: usize) -> Stop {
+        for i in 0..n {
+            if let Some(stop) = self.single() {
+                return stop;
+            }
+            if i + 1 < n && self.breakpoints.contains(&self.cpu.ip()) {
+                return Stop::Breakpoint(self.cpu.ip());
+            }
+        }
+        Stop::Stepped
+    }
+
+    /// Run until something stops the machine. A breakpoint on the current
+    /// instruction doesn't count, so continuing from one makes progress.
+    pub fn cont(&mut self) -> Stop {
+        loop {
+            if let Some(stop) = self.single() {
+                return stop;
+            }
+            if self.breakpoints.contains(&self.cpu.ip()) {
+                return Stop::Breakpoint(self.cpu.ip());
+            }
+        }
+    }
+
+    // Disassemble the instruction at |addr|.
+    fn describe(&self, addr: usize) -> (usize, String) {
+        match Instruction::decode(&self.cpu.memory, addr) {
+            Ok(instruction) => (instruction.width(), instruction.to_string()),
+            Err(_) => (1, format!(".data {}", self.cpu.memory[addr])),
+        }
+    }
+
+    fn regs(&self) -> String {
+        let ip = self.cpu.ip();
+        let next = self.describe(ip).1;
+        format!("ip={:04} rbase={}  {}", ip, self.cpu.rbase(), next)
+    }
+
+    fn list(&self, n: usize) -> String {
+        let mut lines = Vec::new();
+        let mut addr = self.cpu.ip();
+        while lines.len() < n.min(MAX_LIST) {
+            let (width, text) = self.describe(addr);
+            let marker = if self.breakpoints.contains(&addr) {
+                '*'
+            } else {
+                ' '
+            };
+            lines.push(format!("{}{:04}  {}", marker, addr, text));
+            addr = addr.saturating_add(width);
+        }
+        if n > MAX_LIST {
+            lines.push("(truncated)".to_string());
+        }
+        lines.join("\n")
+    }
+
+    fn dump(&self, from: usize, to: usize) -> String {
+        let mut lines = Vec::new();
+        let end = to.min(from.saturating_add(MAX_DUMP));
+        for start in (from..end).step_by(8) {
+            let words: Vec<String> = self
+                .cpu
+                .memory
+                .slice(start, end.min(start.saturating_add(8)))
+                .iter()
+                .map(|w| w.to_string())
+                .collect();
+            lines.push(format!("{:04}: {}", start, words.join(" ")));
+        }
+        if end < to {
+            lines.push("(truncated)".to_string());
+        }
+        lines.join("\n")
+    }
+
+    fn info(&self) -> String {
+        let join = |set: &BTreeSet<usize>| {
+            let v: Vec<String> = set.iter().map(|a| a.to_string()).collect();
+            v.join(" ")
+        };
+        format!(
+            "breakpoints: {}\nwatchpoints: {}",
+            join(&self.breakpoints),
+            join(&self.watchpoints)
+        )
+    }
+}
+
+// Commands need an input queue to feed the program through.
+impl<I: Input + Extend<i64>, O: Output> Debugger<I, O> {
+    /// Execute one command line and return the text to show the user.
+    pub fn command(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return String::new(),
+        };
+        if cmd == "input" {
+            let input: Result<Vec<i64>, _> = words.map(|w| w.parse::<i64>()).collect();
+            return match input {
+                Ok(input) if !input.is_empty() => {
+                    self.cpu.input.extend(input.iter().copied());
+                    let input: Vec<String> = input.iter().map(|w| w.to_string()).collect();
+                    format!("queued {}", input.join(" "))
+                }
+                _ => "input takes one or more words".to_string(),
+            };
+        }
+        let args: Result<Vec<usize>, _> = words.map(|w| w.parse::<usize>()).collect();
+        let args = match args {
+            Ok(args) => args,
+            Err(_) => return "arguments must be unsigned numbers".to_string(),
+        };
+        match (cmd, args.first().copied()) {
+            ("break", Some(a)) | ("b", Some(a)) => {
+                self.add_breakpoint(a);
+                format!("breakpoint at {}", a)
+            }
+            ("delete", Some(a)) | ("d", Some(a)) => match self.remove_breakpoint(a) {
+                true => format!("deleted breakpoint at {}", a),
+                false => format!("no breakpoint at {}", a),
+            },
+            ("watch", Some(a)) | ("w", Some(a)) => {
+                self.add_watchpoint(a);
+                format!("watching {}", a)
+            }
+            ("unwatch", Some(a)) => match self.remove_watchpoint(a) {
+                true => format!("stopped watching {}", a),
+                false => format!("no watchpoint at {}", a),
+            },
+            ("step", n) | ("s", n) => {
+                let stop = self.step(n.unwrap_or(1));
+                format!("{}\n{}", stop, self.regs())
+            }
+            ("continue", _) | ("c", _) => {
+                let stop = self.cont();
+                format!("{}\n{}", stop, self.regs())
+            }
+            ("regs", _) | ("r", _) => self.regs(),
+            ("dump", Some(from)) | ("x", Some(from)) => {
+                let to = args.get(1).copied().unwrap_or(from.saturating_add(8));
+                self.dump(from, to)
+            }
+            ("list", n) | ("l", n) => self.list(n.unwrap_or(5)),
+            ("info", _) | ("i", _) => self.info(),
+            ("help", _) | ("h", _) => HELP.to_string(),
+            _ => format!("unknown command `{}`, try `help`", line.trim()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::doubler;
+    use std::collections::VecDeque;
+
+    // The doubler with three inputs queued, the last ending it.
+    fn debugger() -> Debugger<VecDeque<i64>, Vec<i64>> {
+        let cpu = Cpu::new(doubler())
+            .with_input(VecDeque::from(vec![3, 5, 0]))
+            .with_output(Vec::new());
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut dbg = debugger();
+        dbg.add_breakpoint(9);
+        assert_eq!(dbg.cont(), Stop::Breakpoint(9));
+        assert_eq!(dbg.cpu.memory[15], 6);
+        assert!(dbg.cpu.output.is_empty());
+        assert_eq!(dbg.cont(), Stop::Breakpoint(9));
+        assert_eq!(dbg.cpu.output, vec![6]);
+        assert!(dbg.remove_breakpoint(9));
+        assert_eq!(dbg.cont(), Stop::Halted);
+        assert_eq!(dbg.cpu.output, vec![6, 10]);
+        assert_eq!(dbg.cont(), Stop::Halted);
+    }
+
+    #[test]
+    fn stepping_and_watchpoints() {
+        let mut dbg = debugger();
+        assert_eq!(dbg.step(2), Stop::Stepped);
+        assert_eq!(dbg.cpu.ip(), 5);
+        dbg.add_watchpoint(15);
+        assert_eq!(dbg.step(10), Stop::Watchpoint(15, 6));
+        assert_eq!(dbg.cpu.ip(), 9);
+        assert_eq!(dbg.cont(), Stop::Watchpoint(15, 5));
+        assert_eq!(dbg.cpu.ip(), 2);
+    }
+
+    #[test]
+    fn needs_input() {
+        let mut dbg = debugger();
+        dbg.cpu.input.clear();
+        assert_eq!(dbg.cont(), Stop::NeedsInput);
+        assert_eq!(dbg.cpu.ip(), 0);
+        assert_eq!(dbg.command("input 4 -1 x"), "input takes one or more words");
+        assert_eq!(dbg.command("input 4 0"), "queued 4 0");
+        assert_eq!(dbg.command("c"), "halted\nip=0014 rbase=0  hlt");
+        assert_eq!(dbg.cpu.output, vec![8]);
+    }
+
+    #[test]
+    fn commands() {
+        let mut dbg = debugger();
+        assert_eq!(dbg.command("regs"), "ip=0000 rbase=0  in [15]");
+        assert_eq!(dbg.command("b 11"), "breakpoint at 11");
+        assert_eq!(
+            dbg.command("c"),
+            "breakpoint at 11\nip=0011 rbase=0  jt #1, #0"
+        );
+        assert_eq!(dbg.command("s"), "stepped\nip=0000 rbase=0  in [15]");
+        assert_eq!(dbg.command("x 12 16"), "0012: 1 0 99 6");
+        assert_eq!(dbg.command("l 2"), " 0000  in [15]\n 0002  jf [15], #14");
+        assert_eq!(dbg.command("w 15"), "watching 15");
+        assert_eq!(dbg.command("info"), "breakpoints: 11\nwatchpoints: 15");
+        assert_eq!(dbg.command("d 11"), "deleted breakpoint at 11");
+        assert!(dbg.command("frobnicate").starts_with("unknown command"));
+        assert!(dbg.command("b -1").starts_with("arguments"));
+        let top = usize::MAX - 3;
+        assert_eq!(
+            dbg.command(&format!("x {}", top)),
+            format!("{}: 0 0 0", top)
+        );
+
+        // Huge requests are cut short rather than left to run for ages
+        let list = dbg.command("l 1000000000");
+        assert_eq!(list.lines().count(), MAX_LIST + 1);
+        assert!(list.ends_with("\n(truncated)"));
+        assert!(!dbg
+            .command(&format!("l {}", MAX_LIST))
+            .contains("truncated"));
+        let dump = dbg.command("x 0 10000000000");
+        assert_eq!(dump.lines().count(), MAX_DUMP / 8 + 1);
+        assert!(dump.ends_with("\n(truncated)"));
+        let dump = dbg.command(&format!("x 8 {}", 8 + MAX_DUMP));
+        assert_eq!(dump.lines().count(), MAX_DUMP / 8);
+    }
+}
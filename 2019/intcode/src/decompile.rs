@@ -0,0 +1,1389 @@
+//! A decompiler from Intcode images to structured pseudo-code.
+//!
+//! Code is discovered from address 0 much as `flow` does, except that
+//! calls are recognised by the usual Intcode convention: the caller stores
+//! the return address at `rb+0`, its arguments at `rb+1` onwards and jumps
+//! to the function, which moves the relative base past its frame with `arb`
+//! and returns by jumping through the slot the return address is in. Each
+//! function is then decompiled on its own:
+//!
+//! - `arb` with an immediate operand is tracked rather than shown, so that
+//!   relative operands become `frame[n]`: slot n counting from the relative
+//!   base on entry to the function, where slot 0 holds the return address.
+//! - `jt`/`jf` become `if`, `if`/`else`, `while` and `do`/`while` blocks,
+//!   with `break` and `continue` where they fit. Anything which doesn't
+//!   falls back to labels and `goto`.
+//! - A temporary written by `add`, `mul`, `lt` or `eq` and only read by the
+//!   statement after is folded into it as an expression.
+//!
+//! Like `flow`, it only sees the program as written. Code which modifies
+//! itself, or is only reached through computed jumps, won't come out right.
+use crate::cpu::{Instruction, Parameter};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Write};
+
+/// Somewhere a value can be loaded from or stored to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Place {
+    /// A fixed address.
+    Mem(i64),
+    /// A slot of the current function's stack frame.
+    Frame(i64),
+    /// A relative operand where the relative base couldn't be followed.
+    Rel(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const(i64),
+    Load(Place),
+    /// The next input word.
+    Read,
+    Add(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    Assign(Place, Expr),
+    Print(Expr),
+    /// A call to the function at an address, with its arguments.
+    Call(usize, Vec<Expr>),
+    Return,
+    Halt,
+    /// An instruction which faults whenever it runs, such as one writing
+    /// to an immediate operand.
+    Fault,
+    If(Expr, Vec<Node>, Vec<Node>),
+    While(Expr, Vec<Node>),
+    DoWhile(Vec<Node>, Expr),
+    Break,
+    Continue,
+    Goto(usize),
+    /// A jump to a computed address.
+    GotoIndirect(Expr),
+    Label(usize),
+    /// The relative base moved by an amount only known at run time.
+    Rebase(Expr),
+    // An instruction with nothing to show, kept until labels are placed.
+    Nop,
+}
+
+/// A statement and the address of the instruction it starts at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub addr: usize,
+    pub stmt: Stmt,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: usize,
+    pub body: Vec<Node>,
+}
+
+fn not(e: Expr) -> Expr {
+    match e {
+        Expr::Not(inner) => *inner,
+        Expr::Const(c) => Expr::Const((c == 0) as i64),
+        e => Expr::Not(Box::new(e)),
+    }
+}
+
+// Drop adding 0 and multiplying by 1, the usual ways to copy a value.
+fn simplify(e: Expr) -> Expr {
+    match e {
+        Expr::Add(a, b) if *a == Expr::Const(0) => *b,
+        Expr::Add(a, b) if *b == Expr::Const(0) => *a,
+        Expr::Mul(a, b) if *a == Expr::Const(1) => *b,
+        Expr::Mul(a, b) if *b == Expr::Const(1) => *a,
+        e => e,
+    }
+}
+
+// Whether an always/never taken jump is taken, or None if it depends on
+// the condition.
+fn taken(test: bool, cond: Parameter) -> Option<bool> {
+    match cond {
+        Parameter::Immediate(c) => Some((c != 0) == test),
+        _ => None,
+    }
+}
+
+// The condition under which a jump is taken.
+fn condition(test: bool, cond: Expr) -> Expr {
+    if test {
+        cond
+    } else {
+        not(cond)
+    }
+}
+
+// Decoded instructions of one function, by address.
+type Code = BTreeMap<usize, Instruction>;
+
+// Call jumps, with the function called and the address of the store of the
+// return address before them.
+type Calls = HashMap<usize, (usize, usize)>;
+
+// Where control goes after |instruction| at |addr|, within its function:
+// a call returns to the next instruction.
+fn flow(addr: usize, instruction: Instruction, calls: &Calls) -> Vec<usize> {
+    let next = addr + instruction.width();
+    match instruction {
+        Instruction::JUMP(_, _) if calls.contains_key(&addr) => vec![next],
+        Instruction::JUMP(test, [cond, target]) => {
+            let mut to = Vec::new();
+            if taken(test, cond) != Some(false) {
+                if let Parameter::Immediate(t) = target {
+                    if t >= 0 {
+                        to.push(t as usize);
+                    }
+                }
+            }
+            if taken(test, cond) != Some(true) {
+                to.push(next);
+            }
+            to
+        }
+        Instruction::HALT => vec![],
+        _ => vec![next],
+    }
+}
+
+struct Decompiler<'a> {
+    image: &'a [i64],
+    calls: Calls,
+}
+
+impl<'a> Decompiler<'a> {
+    // The call made by the jump at |addr|, if it follows the convention.
+    fn call_at(&self, addr: usize, instruction: Instruction) -> Option<(usize, usize)> {
+        let target = match instruction {
+            Instruction::JUMP(test, [cond, Parameter::Immediate(t)]) if t >= 0 => {
+                if taken(test, cond) != Some(true) {
+                    return None;
+                }
+                t as usize
+            }
+            _ => return None,
+        };
+        let store = addr.checked_sub(4)?;
+        let ret = (addr + 3) as i64;
+        let value = match Instruction::decode(self.image, store).ok()? {
+            Instruction::ADD(
+                [Parameter::Immediate(a), Parameter::Immediate(b), Parameter::Relative(_)],
+            ) => a.wrapping_add(b),
+            Instruction::MUL(
+                [Parameter::Immediate(a), Parameter::Immediate(b), Parameter::Relative(_)],
+            ) => a.wrapping_mul(b),
+            _ => return None,
+        };
+        if value == ret {
+            Some((target, store))
+        } else {
+            None
+        }
+    }
+
+    // Where control goes after |instruction| at |addr|, noting it if it's
+    // a call.
+    fn successors(&mut self, addr: usize, instruction: Instruction) -> Vec<usize> {
+        if let Some(call) = self.call_at(addr, instruction) {
+            self.calls.insert(addr, call);
+        }
+        flow(addr, instruction, &self.calls)
+    }
+
+    // Every instruction of the function at |entry|, and the functions it calls.
+    fn explore(&mut self, entry: usize) -> (Code, Vec<usize>) {
+        let mut code = Code::new();
+        let mut callees = Vec::new();
+        let mut work = vec![entry];
+        while let Some(addr) = work.pop() {
+            if code.contains_key(&addr) {
+                continue;
+            }
+            let instruction = match Instruction::decode(self.image, addr) {
+                Ok(instruction) => instruction,
+                Err(_) => continue,
+            };
+            code.insert(addr, instruction);
+            work.extend(self.successors(addr, instruction));
+            if let Some(&(callee, _)) = self.calls.get(&addr) {
+                callees.push(callee);
+            }
+        }
+        (code, callees)
+    }
+
+    // The relative base at each instruction as an offset from its value on
+    // entry, or None where it can't be known.
+    fn offsets(&mut self, entry: usize, code: &Code) -> HashMap<usize, Option<i64>> {
+        let mut offsets: HashMap<usize, Option<i64>> = HashMap::new();
+        let mut work = vec![(entry, Some(0))];
+        while let Some((addr, offset)) = work.pop() {
+            let instruction = match code.get(&addr) {
+                Some(&instruction) => instruction,
+                None => continue,
+            };
+            let merged = match offsets.get(&addr) {
+                None => offset,
+                Some(&known) if known == offset => continue,
+                Some(_) => None,
+            };
+            if offsets.get(&addr) == Some(&merged) {
+                continue;
+            }
+            offsets.insert(addr, merged);
+            let after = match instruction {
+                Instruction::RELBASE([Parameter::Immediate(n)]) => merged.map(|o| o + n),
+                Instruction::RELBASE(_) => None,
+                _ => merged,
+            };
+            for to in self.successors(addr, instruction) {
+                work.push((to, after));
+            }
+        }
+        offsets
+    }
+}
+
+// Turning a single function's instructions into statements.
+struct Body<'a> {
+    code: &'a Code,
+    offsets: &'a HashMap<usize, Option<i64>>,
+    calls: &'a Calls,
+    stores: BTreeSet<usize>,
+    // Places live after each instruction.
+    live: Liveness,
+    // Addresses of instruction words anywhere in the program, which are
+    // never treated as temporaries.
+    words: &'a BTreeSet<i64>,
+    // The jump closing each do/while loop found by `region`, by the loop's
+    // head.
+    closes: HashMap<usize, usize>,
+}
+
+#[derive(Clone, Copy)]
+struct Loop {
+    exit: Option<usize>,
+    head: Option<usize>,
+}
+
+impl<'a> Body<'a> {
+    fn place(&self, addr: usize, p: Parameter) -> Option<Place> {
+        match p {
+            Parameter::Position(a) => Some(Place::Mem(a)),
+            Parameter::Immediate(_) => None,
+            Parameter::Relative(k) => Some(match self.offsets.get(&addr).copied().flatten() {
+                Some(offset) => Place::Frame(offset + k),
+                None => Place::Rel(k),
+            }),
+        }
+    }
+
+    fn expr(&self, addr: usize, p: Parameter) -> Expr {
+        match p {
+            Parameter::Immediate(v) => Expr::Const(v),
+            _ => Expr::Load(self.place(addr, p).unwrap()),
+        }
+    }
+
+    // The statement for a straight line instruction.
+    fn simple(&self, addr: usize, instruction: Instruction) -> Stmt {
+        let binary = |args: [Parameter; 3], op: fn(Box<Expr>, Box<Expr>) -> Expr| {
+            let value = simplify(op(
+                Box::new(self.expr(addr, args[0])),
+                Box::new(self.expr(addr, args[1])),
+            ));
+            match self.place(addr, args[2]) {
+                Some(place) => Stmt::Assign(place, value),
+                None => Stmt::Fault,
+            }
+        };
+        match instruction {
+            Instruction::ADD(args) => binary(args, Expr::Add),
+            Instruction::MUL(args) => binary(args, Expr::Mul),
+            Instruction::LESSTHAN(args) => binary(args, Expr::Lt),
+            Instruction::EQUALS(args) => binary(args, Expr::Eq),
+            Instruction::INPUT([dest]) => match self.place(addr, dest) {
+                Some(place) => Stmt::Assign(place, Expr::Read),
+                None => Stmt::Fault,
+            },
+            Instruction::OUTPUT([value]) => Stmt::Print(self.expr(addr, value)),
+            Instruction::RELBASE([Parameter::Immediate(_)])
+                if self.offsets.get(&addr).copied().flatten().is_some() =>
+            {
+                Stmt::Nop
+            }
+            Instruction::RELBASE([offset]) => Stmt::Rebase(self.expr(addr, offset)),
+            Instruction::HALT => Stmt::Halt,
+            Instruction::JUMP(..) => unreachable!(),
+        }
+    }
+
+    // The last jump in [head, to) back to |head|, other than a call.
+    fn back_edge(&self, head: usize, to: usize) -> Option<usize> {
+        self.code
+            .range(head..to)
+            .rev()
+            .find(|(addr, instruction)| match instruction {
+                Instruction::JUMP(test, [cond, Parameter::Immediate(t)]) => {
+                    *t == head as i64
+                        && taken(*test, *cond) != Some(false)
+                        && !self.calls.contains_key(addr)
+                }
+                _ => false,
+            })
+            .map(|(&addr, _)| addr)
+    }
+
+    // Statements for the instructions in [from, to). |looping| is the loop
+    // whose body this is, if any, and |head| a loop header already taken
+    // care of by the caller.
+    fn region(&mut self, from: usize, to: usize, looping: Loop, head: Option<usize>) -> Vec<Node> {
+        let mut out = Vec::new();
+        let mut pos = from;
+        while pos < to {
+            let (&addr, &instruction) = match self.code.range(pos..to).next() {
+                Some(entry) => entry,
+                None => break,
+            };
+            let next = addr + instruction.width();
+            pos = next;
+
+            if head != Some(addr) {
+                if let Some(jump) = self.back_edge(addr, to) {
+                    let (test, cond) = match self.code[&jump] {
+                        Instruction::JUMP(test, [cond, _]) => (test, cond),
+                        _ => unreachable!(),
+                    };
+                    let exit = jump + 3;
+                    let stmt = match taken(test, cond) {
+                        Some(true) => {
+                            let inner = Loop {
+                                exit: Some(exit),
+                                head: Some(addr),
+                            };
+                            Stmt::While(Expr::Const(1), self.region(addr, jump, inner, Some(addr)))
+                        }
+                        _ => {
+                            let inner = Loop {
+                                exit: Some(exit),
+                                head: None,
+                            };
+                            let body = self.region(addr, jump, inner, Some(addr));
+                            self.closes.insert(addr, jump);
+                            Stmt::DoWhile(body, condition(test, self.expr(jump, cond)))
+                        }
+                    };
+                    out.push(Node { addr, stmt });
+                    pos = exit;
+                    continue;
+                }
+            }
+
+            let (test, cond, target) = match instruction {
+                Instruction::JUMP(test, [cond, target]) => (test, cond, target),
+                _ if self.stores.contains(&addr) => {
+                    out.push(Node {
+                        addr,
+                        stmt: Stmt::Nop,
+                    });
+                    continue;
+                }
+                _ => {
+                    let stmt = self.simple(addr, instruction);
+                    out.push(Node { addr, stmt });
+                    continue;
+                }
+            };
+
+            if let Some(&(callee, _)) = self.calls.get(&addr) {
+                let stmt = Stmt::Call(callee, vec![]);
+                out.push(Node { addr, stmt });
+                continue;
+            }
+            let always = match taken(test, cond) {
+                Some(false) => continue,
+                Some(true) => true,
+                None => false,
+            };
+            let target = match target {
+                Parameter::Immediate(t) if t >= 0 => t as usize,
+                _ => {
+                    let jump = match self.place(addr, target) {
+                        Some(Place::Frame(0)) => Stmt::Return,
+                        _ => Stmt::GotoIndirect(self.expr(addr, target)),
+                    };
+                    let stmt = match always {
+                        true => jump,
+                        false => Stmt::If(
+                            condition(test, self.expr(addr, cond)),
+                            vec![Node { addr, stmt: jump }],
+                            vec![],
+                        ),
+                    };
+                    out.push(Node { addr, stmt });
+                    continue;
+                }
+            };
+
+            // Jumps out of or back to the top of the enclosing loop
+            let escape = if Some(target) == looping.exit {
+                Some(Stmt::Break)
+            } else if Some(target) == looping.head {
+                Some(Stmt::Continue)
+            } else {
+                None
+            };
+            if always {
+                let stmt = match escape {
+                    Some(stmt) => stmt,
+                    // Just skipping to where the region ends anyway
+                    None if target == to && self.code.range(next..to).next().is_none() => Stmt::Nop,
+                    None => Stmt::Goto(target),
+                };
+                out.push(Node { addr, stmt });
+                continue;
+            }
+
+            let cond = condition(test, self.expr(addr, cond));
+            let stmt = match escape {
+                Some(stmt) => Stmt::If(cond, vec![Node { addr, stmt }], vec![]),
+                None if target > next && target <= to => {
+                    // Skipping forward over a block, which may itself end by
+                    // jumping over an else block
+                    let skip =
+                        self.code
+                            .range(next..target)
+                            .next_back()
+                            .and_then(|(&at, i)| match i {
+                                Instruction::JUMP(test, [cond, Parameter::Immediate(u)])
+                                    if taken(*test, *cond) == Some(true)
+                                        && !self.calls.contains_key(&at)
+                                        && *u > target as i64
+                                        && *u <= to as i64
+                                        && Some(*u as usize) != looping.exit
+                                        && Some(*u as usize) != looping.head =>
+                                {
+                                    Some((at, *u as usize))
+                                }
+                                _ => None,
+                            });
+                    match skip {
+                        Some((at, end)) => {
+                            pos = end;
+                            Stmt::If(
+                                not(cond),
+                                self.region(next, at, looping, None),
+                                self.region(target, end, looping, None),
+                            )
+                        }
+                        None => {
+                            pos = target;
+                            Stmt::If(not(cond), self.region(next, target, looping, None), vec![])
+                        }
+                    }
+                }
+                None => Stmt::If(
+                    cond,
+                    vec![Node {
+                        addr,
+                        stmt: Stmt::Goto(target),
+                    }],
+                    vec![],
+                ),
+            };
+            out.push(Node { addr, stmt });
+        }
+        out
+    }
+
+    // Give each call the assignments to the slots after its return address
+    // which lead up to it as arguments.
+    fn arguments(&self, nodes: Vec<Node>) -> Vec<Node> {
+        let mut out: Vec<Node> = Vec::new();
+        for mut node in nodes {
+            node.stmt = match node.stmt {
+                Stmt::If(c, a, b) => Stmt::If(c, self.arguments(a), self.arguments(b)),
+                Stmt::While(c, body) => Stmt::While(c, self.arguments(body)),
+                Stmt::DoWhile(body, c) => Stmt::DoWhile(self.arguments(body), c),
+                Stmt::Call(callee, _) => {
+                    Stmt::Call(callee, self.take_arguments(&mut out, node.addr))
+                }
+                stmt => stmt,
+            };
+            out.push(node);
+        }
+        out
+    }
+
+    fn take_arguments(&self, out: &mut Vec<Node>, addr: usize) -> Vec<Expr> {
+        let base = match self.offsets.get(&addr).copied().flatten() {
+            Some(offset) => offset,
+            None => return vec![],
+        };
+        let mut taken = Vec::new();
+        while let Some(Node {
+            stmt: Stmt::Assign(Place::Frame(slot), _),
+            ..
+        }) = out.last()
+        {
+            if *slot <= base || *slot > base + MAX_ARGUMENTS {
+                break;
+            }
+            taken.push(out.pop().unwrap());
+        }
+        let mut args = BTreeMap::new();
+        for node in taken.iter().rev() {
+            if let Stmt::Assign(Place::Frame(slot), value) = &node.stmt {
+                args.insert(*slot, value.clone());
+            }
+        }
+        // Only a run of arguments from the first slot on, each set once, is
+        // taken to be one
+        let run = (1..).take_while(|n| args.contains_key(&(base + n))).count();
+        if run < args.len() || args.len() < taken.len() {
+            out.extend(taken.into_iter().rev());
+            return vec![];
+        }
+        args.into_values().collect()
+    }
+
+    // The places |instruction| loads and the one it stores, if known. |leave|
+    // is what's live once control leaves the function or enters another,
+    // with the frame slots the current function can't see cut out.
+    fn effects(
+        &self,
+        addr: usize,
+        instruction: Instruction,
+        leave: &dyn Fn(Option<i64>, bool) -> Vec<Place>,
+    ) -> (Vec<Place>, Option<Place>) {
+        let mut uses: Vec<Place> = instruction
+            .sources()
+            .iter()
+            .filter_map(|&p| self.place(addr, p))
+            .collect();
+        let set = match instruction {
+            Instruction::ADD(args)
+            | Instruction::MUL(args)
+            | Instruction::LESSTHAN(args)
+            | Instruction::EQUALS(args) => self.place(addr, args[2]),
+            Instruction::INPUT([dest]) => self.place(addr, dest),
+            _ => None,
+        };
+        let call = self.calls.contains_key(&addr);
+        let leaves = match instruction {
+            Instruction::JUMP(test, [cond, target]) => {
+                call || (taken(test, cond) != Some(false)
+                    && !matches!(target, Parameter::Immediate(_)))
+            }
+            _ => false,
+        };
+        if leaves {
+            uses.extend(leave(self.offsets.get(&addr).copied().flatten(), call));
+        }
+        (uses, set)
+    }
+
+    // The places live before and after each instruction, that is read
+    // before they're written on some path on from it. |globals| are the
+    // fixed addresses live where control leaves the function.
+    fn liveness(&self, globals: &HashSet<Place>) -> (Liveness, Liveness) {
+        let slots: BTreeSet<i64> = self
+            .code
+            .iter()
+            .flat_map(|(&addr, i)| i.parameters().iter().map(move |&p| (addr, p)))
+            .filter_map(|(addr, p)| match self.place(addr, p) {
+                Some(Place::Frame(slot)) => Some(slot),
+                _ => None,
+            })
+            .collect();
+        // Slots from there on are the function's own frame, which its caller
+        // doesn't look at, unless it never set one up
+        let frame = match self.offsets.values().flatten().max() {
+            Some(&size) if size > 0 => size,
+            _ => i64::MAX,
+        };
+        // Beyond the globals, a function being called can see the slots from
+        // the current relative base on, and one being returned to the slots
+        // of this one's frame it passed in. Where the relative base is
+        // unknown that could be any of them.
+        let leave = |offset: Option<i64>, call: bool| -> Vec<Place> {
+            let seen = match (offset, call) {
+                (None, _) => slots.iter().collect::<Vec<_>>(),
+                (Some(o), true) => slots.range(o + 1..).collect(),
+                (Some(o), false) => slots.range(o + 1..frame.max(o + 1)).collect(),
+            };
+            let seen = seen.into_iter().map(|&slot| Place::Frame(slot));
+            globals.iter().copied().chain(seen).collect()
+        };
+        let mut live_in: HashMap<usize, HashSet<Place>> = HashMap::new();
+        let mut live_out = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (&addr, &instruction) in self.code.iter().rev() {
+                let mut after = HashSet::new();
+                for to in flow(addr, instruction, self.calls) {
+                    after.extend(live_in.get(&to).into_iter().flatten().copied());
+                }
+                let (uses, set) = self.effects(addr, instruction, &leave);
+                let mut before = after.clone();
+                if let Some(place) = set {
+                    before.remove(&place);
+                }
+                before.extend(uses);
+                if live_in.get(&addr) != Some(&before) {
+                    live_in.insert(addr, before);
+                    changed = true;
+                }
+                live_out.insert(addr, after);
+            }
+        }
+        (live_in, live_out)
+    }
+
+    // Fold temporaries into the statement after them.
+    fn fold(&self, nodes: Vec<Node>) -> Vec<Node> {
+        let mut out: Vec<Node> = Vec::new();
+        for mut node in nodes {
+            node.stmt = match node.stmt {
+                Stmt::If(c, a, b) => Stmt::If(c, self.fold(a), self.fold(b)),
+                Stmt::While(c, body) => loop_condition(c, self.fold(body)),
+                Stmt::DoWhile(body, mut c) => {
+                    let mut body = self.fold(body);
+                    let jump = self.closes.get(&node.addr).copied();
+                    if let (Some(jump), Some(last)) = (jump, body.last()) {
+                        if self.fold_into(&last.stmt, &mut c, jump, None) {
+                            body.pop();
+                        }
+                    }
+                    Stmt::DoWhile(body, c)
+                }
+                stmt => stmt,
+            };
+            // Keep folding the statement before into this one while it can
+            while let Some(prev) = out.last() {
+                let overwrites = match node.stmt {
+                    Stmt::Assign(place, _) => Some(place),
+                    _ => None,
+                };
+                let folded = match node.stmt.operands()[..] {
+                    [ref mut target] => self.fold_into(&prev.stmt, target, node.addr, overwrites),
+                    _ => false,
+                };
+                if !folded {
+                    break;
+                }
+                out.pop();
+            }
+            // Folding can leave a copy of a place onto itself
+            if let Stmt::Assign(place, Expr::Load(from)) = node.stmt {
+                if place == from {
+                    continue;
+                }
+            }
+            out.push(node);
+        }
+        out
+    }
+
+    // Fold the assignment |prev|, if it is one, into |target|, which is
+    // evaluated by the instruction at |addr|. It can go if the temporary
+    // it writes is loaded exactly once there, and is dead after.
+    fn fold_into(
+        &self,
+        prev: &Stmt,
+        target: &mut Expr,
+        addr: usize,
+        overwrites: Option<Place>,
+    ) -> bool {
+        let (place, value) = match prev {
+            Stmt::Assign(place, value) => (*place, value),
+            _ => return false,
+        };
+        match place {
+            Place::Mem(a) if self.words.contains(&a) => return false,
+            Place::Rel(_) => return false,
+            _ => {}
+        }
+        let dead = overwrites == Some(place) || !self.live[&addr].contains(&place);
+        if target.loads(place) != 1 || !dead {
+            return false;
+        }
+        if value.reads_input() && target.reads_input() {
+            return false;
+        }
+        target.substitute(place, value);
+        true
+    }
+}
+
+// The places live at each instruction of a function.
+type Liveness = HashMap<usize, HashSet<Place>>;
+
+// Most arguments a call is taken to have.
+const MAX_ARGUMENTS: i64 = 8;
+
+// Every jump target named by a goto in |nodes|.
+fn gotos(nodes: &[Node], targets: &mut BTreeSet<usize>) {
+    for node in nodes {
+        match &node.stmt {
+            Stmt::Goto(target) => {
+                targets.insert(*target);
+            }
+            Stmt::If(_, a, b) => {
+                gotos(a, targets);
+                gotos(b, targets);
+            }
+            Stmt::While(_, body) | Stmt::DoWhile(body, _) => gotos(body, targets),
+            _ => {}
+        }
+    }
+}
+
+// Put a label before the first statement at each of |targets|, then drop
+// the statements with nothing to show.
+fn place_labels(nodes: Vec<Node>, targets: &mut BTreeSet<usize>) -> Vec<Node> {
+    let mut out = Vec::new();
+    for mut node in nodes {
+        if targets.remove(&node.addr) {
+            out.push(Node {
+                addr: node.addr,
+                stmt: Stmt::Label(node.addr),
+            });
+        }
+        node.stmt = match node.stmt {
+            Stmt::If(c, a, b) => Stmt::If(c, place_labels(a, targets), place_labels(b, targets)),
+            Stmt::While(c, body) => Stmt::While(c, place_labels(body, targets)),
+            Stmt::DoWhile(body, c) => Stmt::DoWhile(place_labels(body, targets), c),
+            Stmt::Nop => continue,
+            stmt => stmt,
+        };
+        out.push(node);
+    }
+    out
+}
+
+impl Expr {
+    fn children(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Expr::Add(a, b) | Expr::Mul(a, b) | Expr::Lt(a, b) | Expr::Eq(a, b) => vec![a, b],
+            Expr::Not(a) => vec![a],
+            _ => vec![],
+        }
+    }
+
+    fn loads(&self, place: Place) -> usize {
+        match self {
+            Expr::Load(p) => (*p == place) as usize,
+            Expr::Add(a, b) | Expr::Mul(a, b) | Expr::Lt(a, b) | Expr::Eq(a, b) => {
+                a.loads(place) + b.loads(place)
+            }
+            Expr::Not(a) => a.loads(place),
+            _ => 0,
+        }
+    }
+
+    fn reads_input(&self) -> bool {
+        match self {
+            Expr::Read => true,
+            Expr::Add(a, b) | Expr::Mul(a, b) | Expr::Lt(a, b) | Expr::Eq(a, b) => {
+                a.reads_input() || b.reads_input()
+            }
+            Expr::Not(a) => a.reads_input(),
+            _ => false,
+        }
+    }
+
+    // Replace the load of |place| with |value|.
+    fn substitute(&mut self, place: Place, value: &Expr) {
+        if *self == Expr::Load(place) {
+            *self = value.clone();
+            return;
+        }
+        for child in self.children() {
+            child.substitute(place, value);
+        }
+    }
+}
+
+impl Stmt {
+    // The expressions evaluated once, up front, when the statement runs.
+    fn operands(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Stmt::Assign(_, e) | Stmt::Print(e) | Stmt::GotoIndirect(e) | Stmt::Rebase(e) => {
+                vec![e]
+            }
+            Stmt::If(e, _, _) => vec![e],
+            Stmt::Call(_, args) => args.iter_mut().collect(),
+            _ => vec![],
+        }
+    }
+}
+
+// A `while (1)` loop whose body starts by breaking out on some condition
+// is a loop on the opposite condition.
+fn loop_condition(cond: Expr, mut body: Vec<Node>) -> Stmt {
+    if cond == Expr::Const(1) {
+        if let Some(Node {
+            stmt: Stmt::If(c, then, otherwise),
+            ..
+        }) = body.first()
+        {
+            if otherwise.is_empty()
+                && matches!(
+                    then[..],
+                    [Node {
+                        stmt: Stmt::Break,
+                        ..
+                    }]
+                )
+            {
+                let c = not(c.clone());
+                body.remove(0);
+                return Stmt::While(c, body);
+            }
+        }
+    }
+    Stmt::While(cond, body)
+}
+
+/// Decompile |image| into its functions, the entry point first as `main`.
+pub fn decompile(image: &[i64]) -> Vec<Function> {
+    let mut decompiler = Decompiler {
+        image,
+        calls: HashMap::new(),
+    };
+    let mut functions: BTreeMap<usize, Code> = BTreeMap::new();
+    let mut work = vec![0];
+    while let Some(entry) = work.pop() {
+        if functions.contains_key(&entry) {
+            continue;
+        }
+        let (code, callees) = decompiler.explore(entry);
+        functions.insert(entry, code);
+        work.extend(callees);
+    }
+
+    let words: BTreeSet<i64> = functions
+        .values()
+        .flat_map(|code| code.iter())
+        .flat_map(|(&addr, i)| (addr..addr + i.width()).map(|a| a as i64))
+        .collect();
+    let mut bodies = Vec::new();
+    for (&entry, code) in functions.iter() {
+        let offsets = decompiler.offsets(entry, code);
+        let stores: BTreeSet<usize> = code
+            .keys()
+            .filter_map(|addr| decompiler.calls.get(addr).map(|&(_, store)| store))
+            .collect();
+        bodies.push((entry, code, offsets, stores));
+    }
+    let mut bodies: Vec<(usize, Body)> = bodies
+        .iter()
+        .map(|(entry, code, offsets, stores)| {
+            let body = Body {
+                code,
+                offsets,
+                calls: &decompiler.calls,
+                stores: stores.clone(),
+                live: HashMap::new(),
+                words: &words,
+                closes: HashMap::new(),
+            };
+            (*entry, body)
+        })
+        .collect();
+
+    // The globals live where control leaves a function are those live on
+    // entry to any function, or on return from any call, which depends in
+    // turn on the globals live where control leaves them.
+    let returns: Vec<usize> = decompiler.calls.keys().map(|&addr| addr + 3).collect();
+    let mut globals = HashSet::new();
+    loop {
+        let mut seen = HashSet::new();
+        for (entry, body) in bodies.iter_mut() {
+            let (live_in, live_out) = body.liveness(&globals);
+            for addr in returns.iter().chain(Some(&*entry)) {
+                seen.extend(live_in.get(addr).into_iter().flatten().copied());
+            }
+            body.live = live_out;
+        }
+        seen.retain(|place| matches!(place, Place::Mem(_)));
+        if seen == globals {
+            break;
+        }
+        globals = seen;
+    }
+
+    let none = Loop {
+        exit: None,
+        head: None,
+    };
+    bodies
+        .iter_mut()
+        .map(|(entry, body)| {
+            let end = body.code.keys().next_back().map_or(*entry, |&a| a + 1);
+            let nodes = body.region(*entry, end, none, None);
+            let mut targets = BTreeSet::new();
+            gotos(&nodes, &mut targets);
+            let nodes = place_labels(nodes, &mut targets);
+            Function {
+                entry: *entry,
+                body: body.arguments(body.fold(nodes)),
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Place::Mem(a) => write!(f, "mem[{}]", a),
+            Place::Frame(n) => write!(f, "frame[{}]", n),
+            Place::Rel(k) => write!(f, "rb[{}]", k),
+        }
+    }
+}
+
+// How tightly an expression binds, for deciding where brackets go.
+fn precedence(e: &Expr) -> u8 {
+    match e {
+        Expr::Lt(..) | Expr::Eq(..) => 1,
+        Expr::Not(inner) if matches!(**inner, Expr::Lt(..) | Expr::Eq(..)) => 1,
+        Expr::Add(..) => 2,
+        Expr::Mul(..) => 3,
+        _ => 4,
+    }
+}
+
+// Write |e|, bracketed if it binds less tightly than |min|.
+fn write_expr(f: &mut fmt::Formatter, e: &Expr, min: u8) -> fmt::Result {
+    let bracket = precedence(e) < min;
+    if bracket {
+        write!(f, "(")?;
+    }
+    let op = |f: &mut fmt::Formatter, a: &Expr, symbol: &str, b: &Expr, p: u8| {
+        write_expr(f, a, p)?;
+        write!(f, " {} ", symbol)?;
+        write_expr(f, b, p + 1)
+    };
+    match e {
+        Expr::Const(v) => write!(f, "{}", v)?,
+        Expr::Load(place) => write!(f, "{}", place)?,
+        Expr::Read => write!(f, "read()")?,
+        Expr::Add(a, b) => match **b {
+            Expr::Const(v) if v < 0 => {
+                write_expr(f, a, 2)?;
+                write!(f, " - {}", v.unsigned_abs())?;
+            }
+            _ => op(f, a, "+", b, 2)?,
+        },
+        Expr::Mul(a, b) => op(f, a, "*", b, 3)?,
+        Expr::Lt(a, b) => op(f, a, "<", b, 2)?,
+        Expr::Eq(a, b) => op(f, a, "==", b, 2)?,
+        Expr::Not(inner) => match &**inner {
+            Expr::Lt(a, b) => op(f, a, ">=", b, 2)?,
+            Expr::Eq(a, b) => op(f, a, "!=", b, 2)?,
+            inner => {
+                write!(f, "!")?;
+                write_expr(f, inner, 4)?;
+            }
+        },
+    }
+    if bracket {
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self, 0)
+    }
+}
+
+fn name(entry: usize) -> String {
+    match entry {
+        0 => "main".to_string(),
+        _ => format!("f{}", entry),
+    }
+}
+
+fn write_block(out: &mut String, nodes: &[Node], depth: usize) {
+    let pad = "    ".repeat(depth);
+    for node in nodes {
+        match &node.stmt {
+            Stmt::Assign(place, value) => writeln!(out, "{}{} = {};", pad, place, value),
+            Stmt::Print(value) => writeln!(out, "{}print({});", pad, value),
+            Stmt::Call(callee, args) => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                writeln!(out, "{}{}({});", pad, name(*callee), args.join(", "))
+            }
+            Stmt::Return => writeln!(out, "{}return;", pad),
+            Stmt::Halt => writeln!(out, "{}halt;", pad),
+            Stmt::Fault => writeln!(out, "{}fault;", pad),
+            Stmt::If(cond, then, otherwise) => {
+                writeln!(out, "{}if ({}) {{", pad, cond).unwrap();
+                write_block(out, then, depth + 1);
+                if !otherwise.is_empty() {
+                    writeln!(out, "{}}} else {{", pad).unwrap();
+                    write_block(out, otherwise, depth + 1);
+                }
+                writeln!(out, "{}}}", pad)
+            }
+            Stmt::While(cond, body) => {
+                writeln!(out, "{}while ({}) {{", pad, cond).unwrap();
+                write_block(out, body, depth + 1);
+                writeln!(out, "{}}}", pad)
+            }
+            Stmt::DoWhile(body, cond) => {
+                writeln!(out, "{}do {{", pad).unwrap();
+                write_block(out, body, depth + 1);
+                writeln!(out, "{}}} while ({});", pad, cond)
+            }
+            Stmt::Break => writeln!(out, "{}break;", pad),
+            Stmt::Continue => writeln!(out, "{}continue;", pad),
+            Stmt::Goto(target) => writeln!(out, "{}goto L{};", pad, target),
+            Stmt::GotoIndirect(target) => writeln!(out, "{}goto *{};", pad, target),
+            Stmt::Label(addr) => writeln!(out, "L{}:", addr),
+            Stmt::Rebase(offset) => writeln!(out, "{}rb += {};", pad, offset),
+            Stmt::Nop => Ok(()),
+        }
+        .unwrap();
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        writeln!(out, "fn {}() {{", name(self.entry))?;
+        write_block(&mut out, &self.body, 1);
+        writeln!(out, "}}")?;
+        write!(f, "{}", out)
+    }
+}
+
+/// Pseudo-code for every function in |image|, separated by blank lines.
+pub fn pseudocode(image: &[i64]) -> String {
+    let functions: Vec<String> = decompile(image).iter().map(|f| f.to_string()).collect();
+    functions.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn loops_and_branches() {
+        let image = assemble(
+            "       in [n]
+            loop:   lt #0, [n], [t]
+                    jf [t], #done
+                    mul [n], [n], [sq]
+                    add [sq], #1, [sq]
+                    eq [sq], #10, [t]
+                    jf [t], #other
+                    out #1
+                    jt #1, #next
+            other:  out [sq]
+            next:   add [n], #-1, [n]
+                    jt #1, #loop
+            done:   hlt
+            n:      .data 0
+            t:      .data 0
+            sq:     .data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {
+    mem[39] = read();
+    while (0 < mem[39]) {
+        mem[41] = mem[39] * mem[39] + 1;
+        if (mem[41] == 10) {
+            print(1);
+        } else {
+            print(mem[41]);
+        }
+        mem[39] = mem[39] - 1;
+    }
+    halt;
+}
+"
+        );
+    }
+
+    #[test]
+    fn do_while() {
+        // The eq feeding the back edge folds into the loop condition
+        let image = assemble(
+            "loop:   in [n]
+                    out [n]
+                    eq [n], #0, [t]
+                    jf [t], #loop
+                    hlt
+            n:      .data 0
+            t:      .data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {
+    do {
+        mem[12] = read();
+        print(mem[12]);
+    } while (mem[12] != 0);
+    halt;
+}
+"
+        );
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let image = assemble(
+            "top:    in [n]
+                    eq [n], #1, [t]
+                    jt [t], #top
+                    eq [n], #2, [t]
+                    jt [t], #done
+                    out [n]
+                    jt #1, #top
+            done:   hlt
+            n:      .data 0
+            t:      .data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {
+    while (1) {
+        mem[22] = read();
+        if (mem[22] == 1) {
+            continue;
+        }
+        if (mem[22] == 2) {
+            break;
+        }
+        print(mem[22]);
+    }
+    halt;
+}
+"
+        );
+    }
+
+    #[test]
+    fn nested_loops() {
+        let image = assemble(
+            "       add #0, #0, [i]
+            outer:  lt [i], #3, [t]
+                    jf [t], #done
+                    add #0, #0, [j]
+            inner:  lt [j], [i], [t]
+                    jf [t], #next
+                    mul [i], [j], [p]
+                    out [p]
+                    add [j], #1, [j]
+                    jt #1, #inner
+            next:   add [i], #1, [i]
+                    jt #1, #outer
+            done:   hlt
+            i:      .data 0
+            j:      .data 0
+            t:      .data 0
+            p:      .data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {
+    mem[43] = 0;
+    while (mem[43] < 3) {
+        mem[44] = 0;
+        while (mem[44] < mem[43]) {
+            print(mem[43] * mem[44]);
+            mem[44] = mem[44] + 1;
+        }
+        mem[43] = mem[43] + 1;
+    }
+    halt;
+}
+"
+        );
+    }
+
+    #[test]
+    fn goto_fallback() {
+        // A loop entered in the middle has no structured form
+        let image = assemble(
+            "       in [n]
+                    jt [n], #mid
+            top:    out #1
+            mid:    out #2
+                    in [n]
+                    jt [n], #top
+                    hlt
+            n:      .data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {
+    if (!read()) {
+L5:
+        print(1);
+    }
+    print(2);
+    if (read()) {
+        goto L5;
+    }
+    halt;
+}
+"
+        );
+    }
+
+    #[test]
+    fn computed_jump() {
+        // Code only reached through the jump isn't found
+        let image = assemble(
+            "       in [n]
+                    mul [n], #3, [n]
+                    add [n], #base, [n]
+                    jt #1, [n]
+            base:   out #0
+                    hlt
+                    out #1
+                    hlt
+            n:      .data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {\n    goto *read() * 3 + 13;\n}\n"
+        );
+    }
+
+    #[test]
+    fn calls() {
+        // main prints square(read() + 2), with the usual calling convention
+        let image = assemble(
+            "       arb #100
+                    in rb+1
+                    add rb+1, #2, rb+1
+                    add #ret, #0, rb+0
+                    jt #1, #square
+            ret:    out rb+1
+                    hlt
+            square: arb #3
+                    mul rb-2, rb-2, rb-1
+                    add rb-1, #0, rb-2
+                    eq rb-2, #0, rb+0
+                    jf rb+0, #out
+                    add #1, #0, rb-2
+            out:    arb #-3
+                    jt #1, rb+0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {
+    f18(read() + 2);
+    print(frame[101]);
+    halt;
+}
+
+fn f18() {
+    frame[2] = frame[1] * frame[1];
+    frame[1] = frame[2];
+    if (frame[1] == 0) {
+        frame[1] = 1;
+    }
+    return;
+}
+"
+        );
+    }
+
+    #[test]
+    fn immediate_destination() {
+        let image = assemble("add #1, #2, #3\nin #0\nhlt").unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {\n    fault;\n    fault;\n    halt;\n}\n"
+        );
+
+        let image = assemble(
+            "       in [n]
+                    jf [n], #skip
+                    eq [n], #1, #7
+            skip:   out [n]
+                    hlt
+            n:      .data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {
+    mem[12] = read();
+    if (mem[12]) {
+        fault;
+    }
+    print(mem[12]);
+    halt;
+}
+"
+        );
+    }
+
+    #[test]
+    fn self_copies() {
+        // Copying a cell onto itself changes nothing, so isn't shown
+        let image = assemble(
+            "       in [x]
+                    add [x], #0, [x]
+                    mul #1, [x], [x]
+                    out [x]
+                    hlt
+            x:      .data 0",
+        )
+        .unwrap();
+        assert_eq!(
+            pseudocode(&image),
+            "fn main() {\n    print(read());\n    halt;\n}\n"
+        );
+    }
+
+    #[test]
+    fn boost() {
+        let image = crate::parse(include_str!("../../9/input.txt"));
+        let functions = decompile(&image);
+        assert_eq!(functions[0].entry, 0);
+        // The recursive function at the end of the image
+        let text = pseudocode(&image);
+        let f922 = &text[text.find("fn f922").unwrap()..];
+        assert_eq!(
+            f922,
+            "fn f922() {
+    if (frame[1] >= 3) {
+        f922(frame[1] - 1);
+        frame[2] = frame[4];
+        f922(frame[1] - 3);
+        frame[1] = frame[4] + frame[2];
+    }
+    return;
+}
+"
+        );
+    }
+}
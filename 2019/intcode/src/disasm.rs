@@ -0,0 +1,150 @@
+//! A static disassembler for Intcode program images.
+//!
+//! Operands are written `[p]` for position mode, `#i` for immediate mode
+//! and `rb+n` for relative mode. Words which don't decode as an instruction
+//! are listed as `.data` so a listing never stops short of the image, as are
+//! opcodes with mode digits beyond their last parameter: the Cpu ignores
+//! them, but the assembler couldn't give them back.
+use crate::cpu::{Instruction, Parameter};
+use std::fmt;
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Parameter::Position(v) => write!(f, "[{}]", v),
+            Parameter::Immediate(v) => write!(f, "#{}", v),
+            Parameter::Relative(v) if *v < 0 => write!(f, "rb{}", v),
+            Parameter::Relative(v) => write!(f, "rb+{}", v),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mnemonic())?;
+        for (i, p) in self.parameters().iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, p)?;
+        }
+        Ok(())
+    }
+}
+
+/// One line of a listing: either a decoded instruction or a data word.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: usize,
+    pub words: Vec<i64>,
+    pub instruction: Option<Instruction>,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let raw: Vec<String> = self.words.iter().map(|w| w.to_string()).collect();
+        write!(f, "{:04}  {:<28}", self.addr, raw.join(" "))?;
+        match &self.instruction {
+            Some(instruction) => write!(f, "{}", instruction),
+            None => write!(f, ".data {}", self.words[0]),
+        }
+    }
+}
+
+// Whether |word| sets mode digits for parameters |instruction| doesn't have.
+fn surplus_modes(word: i64, instruction: &Instruction) -> bool {
+    let arity = instruction.parameters().len() as u32;
+    word / 10i64.pow(arity + 2) != 0
+}
+
+/// Walk |image| from the start, decoding one instruction after another.
+pub fn disassemble(image: &[i64]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut addr = 0;
+    while addr < image.len() {
+        let decoded = Instruction::decode(image, addr)
+            .ok()
+            .filter(|instruction| !surplus_modes(image[addr], instruction));
+        let line = match decoded {
+            Some(instruction) => Line {
+                addr,
+                words: image[addr..addr + instruction.width()].to_vec(),
+                instruction: Some(instruction),
+            },
+            None => Line {
+                addr,
+                words: vec![image[addr]],
+                instruction: None,
+            },
+        };
+        addr += line.words.len();
+        lines.push(line);
+    }
+    lines
+}
+
+/// The full listing for |image|, one line per instruction or data word.
+pub fn listing(image: &[i64]) -> String {
+    disassemble(image)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operand_modes() {
+        let lines = disassemble(&[1002, 4, 3, 4, 21101, -1, 7, 2, 204, -1, 99]);
+        let text: Vec<String> = lines
+            .iter()
+            .map(|l| l.instruction.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            text,
+            vec!["mul [4], #3, [4]", "add #-1, #7, rb+2", "out rb-1", "hlt"]
+        );
+        assert_eq!(lines[1].addr, 4);
+        assert_eq!(lines[1].words, vec![21101, -1, 7, 2]);
+    }
+
+    #[test]
+    fn every_mnemonic() {
+        let image = [
+            3, 0, 4, 0, 1005, 0, 0, 1106, 0, 0, 7, 0, 0, 0, 8, 0, 0, 0, 109, 3, 99,
+        ];
+        let mnemonics: Vec<&str> = disassemble(&image)
+            .iter()
+            .map(|l| l.instruction.as_ref().unwrap().mnemonic())
+            .collect();
+        assert_eq!(
+            mnemonics,
+            vec!["in", "out", "jt", "jf", "lt", "eq", "arb", "hlt"]
+        );
+    }
+
+    #[test]
+    fn data_fallback() {
+        // An invalid opcode and a bad parameter mode
+        let lines = disassemble(&[99, 42, 302, 1, 2, 3, 1, 0]);
+        let text: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        assert_eq!(text[0], format!("0000  {:<28}hlt", "99"));
+        assert_eq!(text[1], format!("0001  {:<28}.data 42", "42"));
+        assert_eq!(text[2], format!("0002  {:<28}.data 302", "302"));
+        assert_eq!(text[3], format!("0003  {:<28}add [2], [3], [1]", "1 2 3 1"));
+        assert_eq!(text[4], format!("0007  {:<28}.data 0", "0"));
+        assert_eq!(lines.len(), 5);
+
+        // Mode digits beyond the last parameter, which the assembler can't
+        // reproduce
+        let lines = disassemble(&[1199, 1104, 99, 21109, 2]);
+        let text: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        assert_eq!(text[0], format!("0000  {:<28}.data 1199", "1199"));
+        assert_eq!(text[1], format!("0001  {:<28}.data 1104", "1104"));
+        assert_eq!(text[3], format!("0003  {:<28}.data 21109", "21109"));
+
+        // An add whose parameters run off the end of the image
+        let lines = disassemble(&[1, 2]);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|l| l.instruction.is_none()));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::cpu::DecodeError;
+use crate::memory::MemoryError;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The opcode or one of its parameter modes is not recognised.
+    Decode(DecodeError),
+    /// A writing instruction was given an immediate mode destination.
+    InvalidDestination,
+    Memory(MemoryError),
+    /// `run` reached an `INPUT` with nothing left to read.
+    InputExhausted,
+    /// A checked result didn't fit in a word, or a widened value too big
+    /// for one was used as one.
+    Overflow,
+    /// The machine used up its cycle budget.
+    CycleLimit,
+    /// The machine came back round to a state it had already been in
+    /// without any I/O since. The fault is at the loop's entry.
+    InfiniteLoop,
+}
+
+/// A fault raised by the instruction at |ip|, whose opcode word was |word|.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmError {
+    pub ip: usize,
+    pub word: i64,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::Decode(DecodeError::InvalidOpcode(_)) => write!(f, "invalid opcode")?,
+            ErrorKind::Decode(DecodeError::InvalidMode(_)) => write!(f, "invalid parameter mode")?,
+            ErrorKind::Decode(DecodeError::Truncated) => write!(f, "truncated instruction")?,
+            ErrorKind::InvalidDestination => write!(f, "invalid destination")?,
+            ErrorKind::Memory(e) => write!(f, "{}", e)?,
+            ErrorKind::InputExhausted => write!(f, "input exhausted")?,
+            ErrorKind::Overflow => write!(f, "arithmetic overflow")?,
+            ErrorKind::CycleLimit => write!(f, "cycle limit reached")?,
+            ErrorKind::InfiniteLoop => write!(f, "infinite loop")?,
+        }
+        write!(f, " at position {} (opcode {})", self.ip, self.word)
+    }
+}
+
+impl std::error::Error for VmError {}
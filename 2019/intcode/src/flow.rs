@@ -0,0 +1,263 @@
+//! Static control-flow analysis of Intcode program images.
+//!
+//! Starting from address 0, every instruction control can reach is decoded
+//! and the image split into basic blocks. `jt` and `jf` with an immediate
+//! target give the graph its edges; a jump through memory or the relative
+//! base can go anywhere, so it is marked unresolved instead. Words no
+//! reachable instruction covers are reported as regions of likely data.
+//!
+//! The analysis only sees the image as written. Programs which modify their
+//! own code before running it will have a different graph at run time.
+use crate::cpu::{Instruction, Parameter};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+    /// Start of the block a branch at the end jumps to.
+    pub taken: Option<usize>,
+    /// Start of the block execution falls through to.
+    pub fallthrough: Option<usize>,
+    /// The block ends in a jump whose target is only known at run time.
+    pub unresolved: bool,
+}
+
+impl Block {
+    /// One past the last word of the block.
+    pub fn end(&self) -> usize {
+        let (addr, last) = self.instructions.last().unwrap();
+        addr + last.width()
+    }
+}
+
+#[derive(Debug)]
+pub struct Graph {
+    /// Basic blocks in address order. The first is the entry point.
+    pub blocks: Vec<Block>,
+    /// Address ranges never reached as code.
+    pub data: Vec<Range<usize>>,
+}
+
+// Where control can go after |instruction|: a branch target if it has one
+// which can be taken (None inside if the target isn't an immediate), and
+// whether execution can carry on to the next instruction.
+fn exits(instruction: &Instruction) -> (Option<Option<usize>>, bool) {
+    match instruction {
+        Instruction::JUMP(test, [cond, target]) => {
+            // A constant condition makes the jump either always or never taken
+            let (taken, falls) = match cond {
+                Parameter::Immediate(c) => ((*c != 0) == *test, (*c != 0) != *test),
+                _ => (true, true),
+            };
+            let target = match target {
+                Parameter::Immediate(t) if *t >= 0 => Some(*t as usize),
+                _ => None,
+            };
+            (if taken { Some(target) } else { None }, falls)
+        }
+        Instruction::HALT => (None, false),
+        _ => (None, true),
+    }
+}
+
+/// Split |image| into basic blocks reachable from address 0.
+pub fn analyze(image: &[i64]) -> Graph {
+    // Find every reachable instruction along with the addresses which must
+    // start a block: the entry, jump targets and the words after jumps.
+    let mut reached = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    let mut work = vec![0];
+    while let Some(addr) = work.pop() {
+        if reached.contains_key(&addr) {
+            continue;
+        }
+        let instruction = match Instruction::decode(image, addr) {
+            Ok(instruction) => instruction,
+            Err(_) => continue,
+        };
+        reached.insert(addr, instruction);
+        let next = addr + instruction.width();
+        let (branch, falls) = exits(&instruction);
+        if let Instruction::JUMP(..) = instruction {
+            leaders.insert(next);
+        }
+        if let Some(Some(target)) = branch {
+            leaders.insert(target);
+            work.push(target);
+        }
+        if falls {
+            work.push(next);
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for &start in leaders.iter().filter(|a| reached.contains_key(a)) {
+        let mut block = Block {
+            start,
+            instructions: Vec::new(),
+            taken: None,
+            fallthrough: None,
+            unresolved: false,
+        };
+        let mut addr = start;
+        loop {
+            let instruction = reached[&addr];
+            block.instructions.push((addr, instruction));
+            let next = addr + instruction.width();
+            let (branch, falls) = exits(&instruction);
+            match branch {
+                Some(Some(target)) if reached.contains_key(&target) => block.taken = Some(target),
+                Some(_) => block.unresolved = true,
+                None => {}
+            }
+            if !falls || !reached.contains_key(&next) {
+                break;
+            }
+            if branch.is_some() || leaders.contains(&next) {
+                block.fallthrough = Some(next);
+                break;
+            }
+            addr = next;
+        }
+        blocks.push(block);
+    }
+
+    // Everything left over is data, or code only reached in ways the
+    // analysis can't follow.
+    let mut code = vec![false; image.len()];
+    for (&addr, instruction) in reached.iter() {
+        for covered in code.iter_mut().skip(addr).take(instruction.width()) {
+            *covered = true;
+        }
+    }
+    let mut data: Vec<Range<usize>> = Vec::new();
+    for (addr, _) in code.iter().enumerate().filter(|(_, &c)| !c) {
+        match data.last_mut() {
+            Some(range) if range.end == addr => range.end += 1,
+            _ => data.push(addr..addr + 1),
+        }
+    }
+    Graph { blocks, data }
+}
+
+impl Graph {
+    /// The block starting at |addr|, if there is one.
+    pub fn block(&self, addr: usize) -> Option<&Block> {
+        self.blocks.iter().find(|b| b.start == addr)
+    }
+
+    /// Graphviz source for the graph. Taken branches are drawn in bold,
+    /// unresolved ones as a dashed edge to a `?` node and data regions as
+    /// notes alongside.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph intcode {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=monospace];").unwrap();
+        for block in self.blocks.iter() {
+            let mut label = String::new();
+            for (addr, instruction) in block.instructions.iter() {
+                write!(label, "{:04}  {}\\l", addr, instruction).unwrap();
+            }
+            writeln!(out, "    b{} [label=\"{}\"];", block.start, label).unwrap();
+            if let Some(to) = block.taken {
+                writeln!(out, "    b{} -> b{} [style=bold];", block.start, to).unwrap();
+            }
+            if let Some(to) = block.fallthrough {
+                writeln!(out, "    b{} -> b{};", block.start, to).unwrap();
+            }
+            if block.unresolved {
+                writeln!(
+                    out,
+                    "    u{0} [shape=circle, label=\"?\"];\n    b{0} -> u{0} [style=dashed];",
+                    block.start
+                )
+                .unwrap();
+            }
+        }
+        for range in self.data.iter() {
+            writeln!(
+                out,
+                "    d{} [shape=note, label=\"data {:04}..{:04}\"];",
+                range.start, range.start, range.end
+            )
+            .unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn loop_and_data() {
+        // 0: out [10]  2: add [10], #-1, [10]  6: jt [10], #0  9: hlt  10: 3
+        let graph = analyze(&[4, 10, 1001, 10, -1, 10, 1005, 10, 0, 99, 3]);
+        assert_eq!(graph.blocks.len(), 2);
+        let entry = &graph.blocks[0];
+        assert_eq!(entry.instructions.len(), 3);
+        assert_eq!(entry.end(), 9);
+        assert_eq!((entry.taken, entry.fallthrough), (Some(0), Some(9)));
+        let exit = graph.block(9).unwrap();
+        assert_eq!((exit.taken, exit.fallthrough), (None, None));
+        assert_eq!(graph.data, vec![10..11]);
+    }
+
+    #[test]
+    fn branches() {
+        let image = assemble(
+            "       in [x]
+                    jf [x], #zero
+                    out #1
+                    jt #1, #done
+            zero:   out #0
+            done:   jt #1, rb+0
+                    out #5
+            x:      .data 0",
+        )
+        .unwrap();
+        let graph = analyze(&image);
+        let starts: Vec<usize> = graph.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0, 5, 10, 12]);
+        assert_eq!(graph.blocks[0].taken, Some(10));
+        assert_eq!(graph.blocks[0].fallthrough, Some(5));
+        // An unconditional jump doesn't fall through
+        assert_eq!(graph.blocks[1].taken, Some(12));
+        assert_eq!(graph.blocks[1].fallthrough, None);
+        assert_eq!(graph.blocks[2].fallthrough, Some(12));
+        // Nor does a return through the stack, which can't be resolved
+        let ret = &graph.blocks[3];
+        assert!(ret.unresolved);
+        assert_eq!((ret.taken, ret.fallthrough), (None, None));
+        // So the code after it is only data as far as the analysis knows
+        assert_eq!(graph.data, vec![15..18]);
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    b0 [label=\"0000  in [17]\\l0002  jf [17], #10\\l\"];\n"));
+        assert!(dot.contains("    b0 -> b10 [style=bold];\n    b0 -> b5;\n"));
+        assert!(dot.contains("    b12 -> u12 [style=dashed];\n"));
+        assert!(dot.contains("    d15 [shape=note, label=\"data 0015..0018\"];\n"));
+    }
+
+    #[test]
+    fn boost() {
+        let image = crate::parse(include_str!("../../9/input.txt"));
+        let graph = analyze(&image);
+        assert_eq!(graph.blocks[0].start, 0);
+        // Every block boundary lines up with a decoded instruction
+        for block in graph.blocks.iter() {
+            for &to in block.taken.iter().chain(block.fallthrough.iter()) {
+                assert!(graph.block(to).is_some(), "dangling edge to {}", to);
+            }
+        }
+        assert!(!graph.data.is_empty());
+    }
+}
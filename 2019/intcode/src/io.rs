@@ -0,0 +1,172 @@
+//! Input sources and output sinks for the `Cpu`.
+//!
+//! A `Cpu` reads words for `INPUT` from anything implementing `Input` and
+//! hands every `OUTPUT` word to something implementing `Output`. The
+//! default pairing is the terminal, but queues, iterators and closures are
+//! provided so that programs can be driven from code and tests.
+use std::collections::VecDeque;
+use std::io::Write;
+
+pub trait Input {
+    /// Produce the next input word, or None if nothing is available.
+    fn read(&mut self) -> Option<i64>;
+}
+
+pub trait Output {
+    fn write(&mut self, value: i64);
+}
+
+impl<T: Input + ?Sized> Input for &mut T {
+    fn read(&mut self) -> Option<i64> {
+        (**self).read()
+    }
+}
+
+impl<T: Output + ?Sized> Output for &mut T {
+    fn write(&mut self, value: i64) {
+        (**self).write(value)
+    }
+}
+
+impl Input for VecDeque<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl Output for VecDeque<i64> {
+    fn write(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+impl Output for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+/// A buffer of words waiting to be read or collected, which can be saved
+/// in a `Snapshot` and refilled when it is restored.
+pub trait Queue {
+    fn pending(&self) -> Vec<i64>;
+    fn refill(words: &[i64]) -> Self;
+}
+
+impl Queue for VecDeque<i64> {
+    fn pending(&self) -> Vec<i64> {
+        self.iter().copied().collect()
+    }
+
+    fn refill(words: &[i64]) -> Self {
+        words.iter().copied().collect()
+    }
+}
+
+impl Queue for Vec<i64> {
+    fn pending(&self) -> Vec<i64> {
+        self.clone()
+    }
+
+    fn refill(words: &[i64]) -> Self {
+        words.to_vec()
+    }
+}
+
+/// Feed input from any iterator of words.
+pub struct Iter<T>(pub T);
+
+impl<T: Iterator<Item = i64>> Input for Iter<T> {
+    fn read(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+/// Feed input from a closure.
+pub struct FnInput<F>(pub F);
+
+impl<F: FnMut() -> Option<i64>> Input for FnInput<F> {
+    fn read(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+/// Hand each output word to a closure.
+pub struct FnOutput<F>(pub F);
+
+impl<F: FnMut(i64)> Output for FnOutput<F> {
+    fn write(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+/// Prompt with `$ ` and read one decimal word per line from stdin. Lines
+/// which aren't a number are rejected and the prompt repeated.
+#[derive(Debug, Clone, Copy)]
+pub struct Stdin;
+
+impl Input for Stdin {
+    fn read(&mut self) -> Option<i64> {
+        loop {
+            print!("$ ");
+            std::io::stdout().flush().unwrap();
+            let mut buffer = String::new();
+            if std::io::stdin().read_line(&mut buffer).unwrap() == 0 {
+                return None;
+            }
+            match buffer.trim().parse() {
+                Ok(value) => return Some(value),
+                Err(_) => eprintln!("not a number: {}", buffer.trim()),
+            }
+        }
+    }
+}
+
+/// Print each output word to stdout as `> n`.
+#[derive(Debug, Clone, Copy)]
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn write(&mut self, value: i64) {
+        println!("> {}", value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue() {
+        let mut q: VecDeque<i64> = vec![1, 2].into();
+        q.write(3);
+        assert_eq!(q.read(), Some(1));
+        assert_eq!(q.read(), Some(2));
+        assert_eq!(q.read(), Some(3));
+        assert_eq!(q.read(), None);
+    }
+
+    #[test]
+    fn iter_and_closures() {
+        let mut it = Iter(vec![5, 6].into_iter());
+        assert_eq!(it.read(), Some(5));
+        assert_eq!(it.read(), Some(6));
+        assert_eq!(it.read(), None);
+
+        let mut n = 0;
+        let mut counter = FnInput(|| {
+            n += 1;
+            Some(n)
+        });
+        assert_eq!(counter.read(), Some(1));
+        assert_eq!(counter.read(), Some(2));
+
+        let mut sum = 0;
+        {
+            let mut out = FnOutput(|v| sum += v);
+            out.write(40);
+            out.write(2);
+        }
+        assert_eq!(sum, 42);
+    }
+}
@@ -0,0 +1,61 @@
+//! A shared Intcode virtual machine for the 2019 puzzles.
+//!
+//! The day crates all parse a comma separated program image and hand it to
+//! a `Cpu` which implements the full instruction set from day 9: position,
+//! immediate and relative parameter modes plus the relative base register.
+//! Programs read and write through the `Input` and `Output` traits so they
+//! can be driven from the terminal, from code or from tests alike.
+pub mod amp;
+pub mod ascii;
+pub mod asm;
+pub mod compiler;
+#[cfg(test)]
+mod conformance;
+pub mod coverage;
+mod cpu;
+pub mod debugger;
+pub mod decompile;
+pub mod disasm;
+mod error;
+pub mod flow;
+pub mod io;
+pub mod memory;
+pub mod net;
+pub mod profile;
+pub mod seek;
+pub mod snapshot;
+#[cfg(test)]
+mod testing;
+pub mod threaded;
+pub mod trace;
+pub mod transcript;
+
+pub use cpu::{Arithmetic, Cpu, DecodeError, Instruction, Parameter, Status};
+pub use error::{ErrorKind, VmError};
+pub use io::{Input, Output};
+pub use memory::Memory;
+pub use snapshot::Snapshot;
+
+/// Parse a comma separated program image into memory words.
+pub fn parse(s: &str) -> Vec<i64> {
+    s.trim()
+        .split(',')
+        .map(|word| word.trim().parse::<i64>().unwrap())
+        .collect()
+}
+
+/// Read and parse a program image from |path|.
+pub fn load<P: AsRef<std::path::Path>>(path: P) -> Vec<i64> {
+    parse(&std::fs::read_to_string(path).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_image() {
+        assert_eq!(parse("1,0,0,3,99\n"), vec![1, 0, 0, 3, 99]);
+        assert_eq!(parse("109, -1, 204,1"), vec![109, -1, 204, 1]);
+    }
+}
@@ -0,0 +1,331 @@
+//! Growable, sparse memory for the `Cpu`.
+//!
+//! The low addresses where programs and their working storage live are kept
+//! in a flat vector which grows as it is written. Anything beyond that is
+//! held in pages allocated on first write, so a program poking at address
+//! 10^12 costs one page rather than terabytes. Reading an address which has
+//! never been written returns 0.
+//!
+//! Under widened arithmetic a cell can hold a value too big for a word. The
+//! full value is kept aside and the cell itself holds its low 64 bits until
+//! it is next written.
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+// Addresses below this live in the flat vector.
+const DENSE_WORDS: usize = 1 << 20;
+const PAGE_SIZE: usize = 4096;
+
+static ZERO: i64 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryError {
+    /// A program computed an address below zero.
+    Negative(i64),
+    /// An address at or beyond the configured limit.
+    OutOfRange(usize),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryError::Negative(addr) => write!(f, "negative address {}", addr),
+            MemoryError::OutOfRange(addr) => {
+                write!(f, "address {} is beyond the memory limit", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct Memory {
+    dense: Vec<i64>,
+    pages: HashMap<usize, Box<[i64]>>,
+    // Cells holding values which don't fit in 64 bits.
+    wide: HashMap<usize, i128>,
+    limit: Option<usize>,
+}
+
+impl From<Vec<i64>> for Memory {
+    fn from(dense: Vec<i64>) -> Memory {
+        Memory {
+            dense,
+            pages: HashMap::new(),
+            wide: HashMap::new(),
+            limit: None,
+        }
+    }
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    /// Refuse any access at or beyond |limit| words.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Check that a computed address is one the program may use.
+    pub fn address(&self, addr: i64) -> Result<usize, MemoryError> {
+        if addr < 0 {
+            return Err(MemoryError::Negative(addr));
+        }
+        let addr = addr as usize;
+        match self.limit {
+            Some(limit) if addr >= limit => Err(MemoryError::OutOfRange(addr)),
+            _ => Ok(addr),
+        }
+    }
+
+    pub fn get(&self, addr: usize) -> i64 {
+        if addr < DENSE_WORDS {
+            return self.dense.get(addr).copied().unwrap_or(0);
+        }
+        self.pages
+            .get(&(addr / PAGE_SIZE))
+            .map_or(0, |page| page[addr % PAGE_SIZE])
+    }
+
+    pub fn set(&mut self, addr: usize, value: i64) -> Result<(), MemoryError> {
+        if let Some(limit) = self.limit {
+            if addr >= limit {
+                return Err(MemoryError::OutOfRange(addr));
+            }
+        }
+        if !self.wide.is_empty() {
+            self.wide.remove(&addr);
+        }
+        *self.slot(addr) = value;
+        Ok(())
+    }
+
+    /// The full value at |addr|, including any too big for a word.
+    pub fn get_wide(&self, addr: usize) -> i128 {
+        if !self.wide.is_empty() {
+            if let Some(&value) = self.wide.get(&addr) {
+                return value;
+            }
+        }
+        self.get(addr) as i128
+    }
+
+    pub fn set_wide(&mut self, addr: usize, value: i128) -> Result<(), MemoryError> {
+        self.set(addr, value as i64)?;
+        if value as i64 as i128 != value {
+            self.wide.insert(addr, value);
+        }
+        Ok(())
+    }
+
+    /// Whether |addr| holds a value too big for a word.
+    pub fn is_wide(&self, addr: usize) -> bool {
+        !self.wide.is_empty() && self.wide.contains_key(&addr)
+    }
+
+    // Whether any cell holds a value too big for a word.
+    pub(crate) fn has_wide(&self) -> bool {
+        !self.wide.is_empty()
+    }
+
+    /// Every cell holding a value too big for a word, in address order.
+    pub fn wide_cells(&self) -> Vec<(usize, i128)> {
+        let mut cells: Vec<(usize, i128)> = self.wide.iter().map(|(&a, &v)| (a, v)).collect();
+        cells.sort();
+        cells
+    }
+
+    // A mutable reference to |addr|, allocating storage for it if needed.
+    fn slot(&mut self, addr: usize) -> &mut i64 {
+        if addr < DENSE_WORDS {
+            if addr >= self.dense.len() {
+                self.dense.resize(addr + 1, 0);
+            }
+            return &mut self.dense[addr];
+        }
+        let page = self
+            .pages
+            .entry(addr / PAGE_SIZE)
+            .or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice());
+        &mut page[addr % PAGE_SIZE]
+    }
+
+    /// One past the highest address which has backing storage. Everything
+    /// at or above it reads as 0.
+    pub fn len(&self) -> usize {
+        match self.pages.keys().max() {
+            Some(page) => (page + 1) * PAGE_SIZE,
+            None => self.dense.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copy out the words in |from|..|to|.
+    pub fn slice(&self, from: usize, to: usize) -> Vec<i64> {
+        (from..to).map(|addr| self.get(addr)).collect()
+    }
+
+    // Whether every cell of |self| reads the same as in |other|, however
+    // the two happen to be stored.
+    pub(crate) fn same_contents(&self, other: &Memory) -> bool {
+        let differs = |a: &Memory, b: &Memory| {
+            a.chunks().into_iter().any(|(start, words)| {
+                (start..start + words.len()).any(|addr| a.get_wide(addr) != b.get_wide(addr))
+            })
+        };
+        !differs(self, other) && !differs(other, self)
+    }
+
+    /// Every word with backing storage, as runs of (start address, words)
+    /// in address order.
+    pub fn chunks(&self) -> Vec<(usize, &[i64])> {
+        let mut chunks = vec![(0, &self.dense[..])];
+        let mut pages: Vec<_> = self.pages.iter().collect();
+        pages.sort_by_key(|&(page, _)| *page);
+        chunks.extend(
+            pages
+                .into_iter()
+                .map(|(page, words)| (page * PAGE_SIZE, &words[..])),
+        );
+        chunks
+    }
+}
+
+impl Index<usize> for Memory {
+    type Output = i64;
+
+    fn index(&self, addr: usize) -> &i64 {
+        if addr < DENSE_WORDS {
+            return self.dense.get(addr).unwrap_or(&ZERO);
+        }
+        self.pages
+            .get(&(addr / PAGE_SIZE))
+            .map_or(&ZERO, |page| &page[addr % PAGE_SIZE])
+    }
+}
+
+// Writing through an index grows memory like `set` but panics if the limit
+// is exceeded.
+impl IndexMut<usize> for Memory {
+    fn index_mut(&mut self, addr: usize) -> &mut i64 {
+        if let Some(limit) = self.limit {
+            if addr >= limit {
+                panic!("{}", MemoryError::OutOfRange(addr));
+            }
+        }
+        if !self.wide.is_empty() {
+            self.wide.remove(&addr);
+        }
+        self.slot(addr)
+    }
+}
+
+/// Anything instructions can be decoded from: a program image or the
+/// memory of a running machine.
+pub trait Words {
+    /// The word at |addr|, or None if it is past the end.
+    fn word(&self, addr: usize) -> Option<i64>;
+}
+
+impl Words for [i64] {
+    fn word(&self, addr: usize) -> Option<i64> {
+        self.get(addr).copied()
+    }
+}
+
+impl Words for Memory {
+    fn word(&self, addr: usize) -> Option<i64> {
+        Some(self.get(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_on_demand() {
+        let mut m = Memory::from(vec![1, 2, 3]);
+        assert_eq!(m.len(), 3);
+        assert_eq!(m.get(5000), 0);
+        assert_eq!(m[5000], 0);
+        m.set(5000, 7).unwrap();
+        assert_eq!(m.len(), 5001);
+        assert_eq!(m[5000], 7);
+        assert_eq!(m[4999], 0);
+        m[6000] = 8;
+        assert_eq!(m.get(6000), 8);
+        assert_eq!(m.slice(0, 4), vec![1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn sparse_pages() {
+        let mut m = Memory::new();
+        let far = 1_000_000_000_000;
+        m.set(far, 42).unwrap();
+        assert_eq!(m.get(far), 42);
+        assert_eq!(m.get(far + 1), 0);
+        assert_eq!(m.get(far - 1), 0);
+        assert_eq!(m.pages.len(), 1);
+        assert!(m.dense.is_empty());
+        assert!(m.len() > far);
+        let chunks = m.chunks();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].0, far / PAGE_SIZE * PAGE_SIZE);
+        assert_eq!(chunks[1].1[far % PAGE_SIZE], 42);
+    }
+
+    #[test]
+    fn addresses_and_limits() {
+        let mut m = Memory::new();
+        assert_eq!(m.address(-3), Err(MemoryError::Negative(-3)));
+        assert_eq!(m.address(12), Ok(12));
+        m.set_limit(Some(10));
+        assert_eq!(m.address(12), Err(MemoryError::OutOfRange(12)));
+        assert_eq!(m.set(10, 1), Err(MemoryError::OutOfRange(10)));
+        assert_eq!(m.set(9, 1), Ok(()));
+    }
+
+    #[test]
+    fn wide_values() {
+        let mut m = Memory::new();
+        let big = i64::MAX as i128 * 4;
+        m.set_wide(3, big).unwrap();
+        assert!(m.is_wide(3));
+        assert_eq!(m.get_wide(3), big);
+        assert_eq!(m.get(3), big as i64);
+        assert_eq!(m.wide_cells(), vec![(3, big)]);
+        m.set_wide(4, -7).unwrap();
+        assert!(!m.is_wide(4));
+        assert_eq!(m.get_wide(4), -7);
+        // Any ordinary write replaces the wide value
+        m[3] = 1;
+        assert!(!m.is_wide(3));
+        assert_eq!(m.get_wide(3), 1);
+    }
+
+    #[test]
+    fn same_contents() {
+        let mut a = Memory::from(vec![1, 2, 3]);
+        let mut b = Memory::from(vec![1, 2, 3, 0, 0]);
+        b.set(1_000_000_000, 0).unwrap();
+        assert!(a.same_contents(&b) && b.same_contents(&a));
+        b.set_wide(4, 1 << 70).unwrap();
+        assert!(!a.same_contents(&b) && !b.same_contents(&a));
+        a.set_wide(4, 1 << 70).unwrap();
+        assert!(a.same_contents(&b));
+        a.set(1_000_000_000, 1).unwrap();
+        assert!(!b.same_contents(&a));
+    }
+}
@@ -0,0 +1,246 @@
+//! A network of machines exchanging addressed packets, as in day 23.
+//!
+//! Every machine boots with its network address as its first input. It
+//! sends a packet by outputting the destination address followed by the
+//! packet's X and Y values, which are queued as input for the machine at
+//! that address. A machine which reads from an empty queue is given -1.
+//!
+//! An optional NAT listens on an address of its own. It remembers the last
+//! packet sent to it and, once every machine has sat waiting on an empty
+//! queue, sends that packet to address 0 to get the network going again.
+use crate::cpu::{Cpu, Status};
+use crate::error::VmError;
+use std::collections::VecDeque;
+
+type Node = Cpu<VecDeque<i64>, Vec<i64>>;
+
+// Consecutive reads of an empty queue before a machine counts as idle. The
+// first -1 a machine reads may just be it polling between jobs.
+const IDLE_READS: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub dest: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Machine |from| sent a packet. Packets for addresses with nothing
+    /// listening are dropped.
+    Sent { from: usize, packet: Packet },
+    /// The network went idle and the NAT resent its last packet to 0.
+    Wake(Packet),
+    /// The network went idle with nothing to wake it.
+    Idle,
+}
+
+pub struct Network {
+    nodes: Vec<Node>,
+    // Words of a partly output packet, per machine.
+    outboxes: Vec<Vec<i64>>,
+    // Consecutive empty reads, per machine.
+    starved: Vec<u32>,
+    nat: Option<i64>,
+    nat_packet: Option<Packet>,
+}
+
+impl Network {
+    // Boot |size| copies of |program| at addresses 0 to |size| - 1.
+    pub fn new(program: &[i64], size: usize) -> Network {
+        let nodes = (0..size)
+            .map(|addr| {
+                Cpu::new(program.to_vec())
+                    .with_input(VecDeque::from(vec![addr as i64]))
+                    .with_output(Vec::new())
+            })
+            .collect();
+        Network {
+            nodes,
+            outboxes: vec![Vec::new(); size],
+            starved: vec![0; size],
+            nat: None,
+            nat_packet: None,
+        }
+    }
+
+    /// Attach a NAT listening on |addr|.
+    pub fn with_nat(mut self, addr: i64) -> Self {
+        self.nat = Some(addr);
+        self
+    }
+
+    /// Deliver |packet| as though a machine had sent it.
+    pub fn send(&mut self, packet: Packet) {
+        if Some(packet.dest) == self.nat {
+            self.nat_packet = Some(packet);
+        } else if let Some(node) = self.node(packet.dest) {
+            self.nodes[node].push_input(packet.x);
+            self.nodes[node].push_input(packet.y);
+            self.starved[node] = 0;
+        }
+    }
+
+    fn node(&self, addr: i64) -> Option<usize> {
+        if addr >= 0 && (addr as usize) < self.nodes.len() {
+            Some(addr as usize)
+        } else {
+            None
+        }
+    }
+
+    fn idle(&self) -> bool {
+        self.starved.iter().all(|&n| n >= IDLE_READS)
+    }
+
+    // Give every machine a turn, running each until it outputs a word or
+    // waits on input. Returns the events of the round in order.
+    pub fn round(&mut self) -> Result<Vec<Event>, VmError> {
+        let mut events = Vec::new();
+        for i in 0..self.nodes.len() {
+            match self.nodes[i].resume()? {
+                Status::Output(word) => {
+                    self.starved[i] = 0;
+                    self.outboxes[i].push(word);
+                    if let [dest, x, y] = self.outboxes[i][..] {
+                        self.outboxes[i].clear();
+                        let packet = Packet { dest, x, y };
+                        self.send(packet);
+                        events.push(Event::Sent { from: i, packet });
+                    }
+                }
+                Status::NeedsInput => {
+                    self.nodes[i].push_input(-1);
+                    self.starved[i] += 1;
+                }
+                // A halted machine will never send anything again.
+                Status::Halted => self.starved[i] = IDLE_READS,
+                Status::Running => unreachable!(),
+            }
+        }
+        if self.idle() {
+            match self.nat_packet {
+                Some(packet) => {
+                    let packet = Packet { dest: 0, ..packet };
+                    self.send(packet);
+                    events.push(Event::Wake(packet));
+                }
+                None => events.push(Event::Idle),
+            }
+        }
+        Ok(events)
+    }
+
+    /// Run rounds until |stop| accepts an event, and return that event.
+    /// Never returns if no event is ever accepted.
+    pub fn run_until<F: FnMut(&Event) -> bool>(&mut self, mut stop: F) -> Result<Event, VmError> {
+        loop {
+            if let Some(event) = self.round()?.into_iter().find(|e| stop(e)) {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    // Each machine passes any packet it receives on to the next address
+    // with Y incremented. The last machine in a network of four sends to
+    // the NAT at 255 instead.
+    fn relay() -> Vec<i64> {
+        assemble(
+            "       in [addr]
+                    add [addr], #1, [dest]
+                    eq [dest], #4, [t]
+                    jf [t], #loop
+                    add #255, #0, [dest]
+            loop:   in [x]
+                    eq [x], #-1, [t]
+                    jt [t], #loop
+                    in [y]
+                    add [y], #1, [y]
+                    out [dest]
+                    out [x]
+                    out [y]
+                    jt #1, #loop
+            addr:   .data 0
+            dest:   .data 0
+            x:      .data 0
+            y:      .data 0
+            t:      .data 0",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn routing() {
+        let mut net = Network::new(&relay(), 4).with_nat(255);
+        net.send(Packet {
+            dest: 0,
+            x: 7,
+            y: 100,
+        });
+        let mut hops = Vec::new();
+        let event = net
+            .run_until(|e| {
+                if let Event::Sent { from, packet } = e {
+                    hops.push((*from, packet.dest, packet.y));
+                }
+                matches!(e, Event::Sent { packet, .. } if packet.dest == 255)
+            })
+            .unwrap();
+        assert_eq!(
+            hops,
+            vec![(0, 1, 101), (1, 2, 102), (2, 3, 103), (3, 255, 104)]
+        );
+        assert_eq!(
+            event,
+            Event::Sent {
+                from: 3,
+                packet: Packet {
+                    dest: 255,
+                    x: 7,
+                    y: 104
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn nat_wakes_idle_network() {
+        let mut net = Network::new(&relay(), 4).with_nat(255);
+        net.send(Packet {
+            dest: 0,
+            x: 7,
+            y: 100,
+        });
+        let mut wakes = Vec::new();
+        net.run_until(|e| {
+            if let Event::Wake(packet) = e {
+                wakes.push(packet.y);
+            }
+            wakes.len() == 3
+        })
+        .unwrap();
+        assert_eq!(wakes, vec![104, 108, 112]);
+    }
+
+    #[test]
+    fn idle_without_nat() {
+        let mut net = Network::new(&relay(), 4);
+        assert_eq!(net.run_until(|_| true), Ok(Event::Idle));
+        // Nothing listens on 255 without a NAT, so the packet is dropped
+        net.send(Packet {
+            dest: 3,
+            x: 1,
+            y: 1,
+        });
+        let event = net.run_until(|e| *e != Event::Idle).unwrap();
+        assert!(matches!(event, Event::Sent { from: 3, .. }));
+        assert_eq!(net.run_until(|_| true), Ok(Event::Idle));
+    }
+}
@@ -0,0 +1,191 @@
+//! Execution profiling for the `Cpu`.
+//!
+//! A `Profile` is a tracer which tallies how often each instruction and
+//! opcode executed, how often each memory address was read and written and
+//! which jumps were taken. Attach one behind an `Arc<Mutex<_>>` so it can
+//! be read back once the program has run.
+use crate::cpu::Instruction;
+use crate::disasm;
+use crate::trace::{Record, Tracer};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::hash::Hash;
+
+#[derive(Debug, Default)]
+pub struct Profile {
+    /// Instructions completed, not counting `HALT`.
+    pub cycles: u64,
+    /// Executions per instruction address.
+    pub executions: HashMap<usize, u64>,
+    /// Executions per mnemonic.
+    pub opcodes: HashMap<&'static str, u64>,
+    pub reads: HashMap<usize, u64>,
+    pub writes: HashMap<usize, u64>,
+    /// Taken jumps per (from, to) pair.
+    pub jumps: HashMap<(usize, usize), u64>,
+    // The instruction last seen at each address, for the report.
+    instructions: HashMap<usize, Instruction>,
+}
+
+impl Tracer for Profile {
+    fn record(&mut self, record: &Record) {
+        let instruction = record.instruction;
+        if instruction != Instruction::HALT {
+            self.cycles += 1;
+        }
+        *self.executions.entry(record.ip).or_insert(0) += 1;
+        *self.opcodes.entry(instruction.mnemonic()).or_insert(0) += 1;
+        self.instructions.insert(record.ip, instruction);
+        for &addr in record.loads.iter() {
+            *self.reads.entry(addr).or_insert(0) += 1;
+        }
+        if let Some((addr, _)) = record.write {
+            *self.writes.entry(addr).or_insert(0) += 1;
+        }
+        if let Instruction::JUMP(test, _) = instruction {
+            // Both operands fit in a word, or the jump would have faulted.
+            if let [Some(value), Some(target)] = record.reads[..] {
+                if (value != 0) == test {
+                    *self.jumps.entry((record.ip, target as usize)).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+}
+
+// Entries of |counts| from most to least frequent, ties in key order.
+fn ranked<K: Copy + Ord + Hash>(counts: &HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut ranked: Vec<(K, u64)> = counts.iter().map(|(&k, &n)| (k, n)).collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    ranked
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    fn percent(&self, n: u64) -> f64 {
+        100.0 * n as f64 / self.cycles.max(1) as f64
+    }
+
+    /// The hottest |top| entries of each table, most frequent first.
+    pub fn report(&self, top: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "cycles {}", self.cycles).unwrap();
+
+        writeln!(out, "\nhot instructions").unwrap();
+        for (addr, n) in ranked(&self.executions).into_iter().take(top) {
+            let text = self.instructions[&addr].to_string();
+            writeln!(
+                out,
+                "{:>12} {:>6.2}%  {:04}  {}",
+                n,
+                self.percent(n),
+                addr,
+                text
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nopcodes").unwrap();
+        for (op, n) in ranked(&self.opcodes) {
+            writeln!(out, "{:>12} {:>6.2}%  {}", n, self.percent(n), op).unwrap();
+        }
+
+        writeln!(out, "\nmemory reads").unwrap();
+        for (addr, n) in ranked(&self.reads).into_iter().take(top) {
+            writeln!(out, "{:>12}  {:04}", n, addr).unwrap();
+        }
+
+        writeln!(out, "\nmemory writes").unwrap();
+        for (addr, n) in ranked(&self.writes).into_iter().take(top) {
+            writeln!(out, "{:>12}  {:04}", n, addr).unwrap();
+        }
+
+        writeln!(out, "\njumps taken").unwrap();
+        for ((from, to), n) in ranked(&self.jumps).into_iter().take(top) {
+            writeln!(out, "{:>12}  {:04} -> {:04}", n, from, to).unwrap();
+        }
+        out
+    }
+
+    /// A listing of |image| with each instruction's execution count in the
+    /// margin, and read and write counts after any data word touched.
+    pub fn annotate(&self, image: &[i64]) -> String {
+        let mut out = String::new();
+        for line in disasm::disassemble(image) {
+            let count = match self.executions.get(&line.addr) {
+                Some(n) => n.to_string(),
+                None => String::new(),
+            };
+            write!(out, "{:>12}  {}", count, line).unwrap();
+            if line.instruction.is_none() {
+                let reads = self.reads.get(&line.addr).copied().unwrap_or(0);
+                let writes = self.writes.get(&line.addr).copied().unwrap_or(0);
+                if reads + writes > 0 {
+                    write!(out, "  ; {} reads, {} writes", reads, writes).unwrap();
+                }
+            }
+            writeln!(out).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use std::sync::{Arc, Mutex};
+
+    // 0: out [10]  2: add [10], #-1, [10]  6: jt [10], #0  9: hlt  10: 3
+    fn countdown() -> Vec<i64> {
+        vec![4, 10, 1001, 10, -1, 10, 1005, 10, 0, 99, 3]
+    }
+
+    fn profile(image: Vec<i64>) -> Profile {
+        let profile = Arc::new(Mutex::new(Profile::new()));
+        Cpu::new(image)
+            .with_output(Vec::new())
+            .with_tracer(profile.clone())
+            .run()
+            .unwrap();
+        Arc::try_unwrap(profile).ok().unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn counts() {
+        let p = profile(countdown());
+        assert_eq!(p.cycles, 9);
+        assert_eq!(p.executions[&0], 3);
+        assert_eq!(p.executions[&9], 1);
+        assert_eq!(p.opcodes["jt"], 3);
+        assert_eq!(p.opcodes["hlt"], 1);
+        // out, add and jt each read the counter
+        assert_eq!(p.reads[&10], 9);
+        assert_eq!(p.writes[&10], 3);
+        assert_eq!(p.jumps.get(&(6, 0)), Some(&2));
+        assert_eq!(p.jumps.len(), 1);
+    }
+
+    #[test]
+    fn reports() {
+        let p = profile(countdown());
+        let report = p.report(2);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "cycles 9");
+        assert_eq!(lines[2], "hot instructions");
+        assert_eq!(lines[3], "           3  33.33%  0000  out [10]");
+        assert_eq!(lines[4], "           3  33.33%  0002  add [10], #-1, [10]");
+        assert!(report.contains("           2  0006 -> 0000\n"));
+
+        let listing = p.annotate(&countdown());
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], format!("{:>12}  {:<34}out [10]", 3, "0000  4 10"));
+        assert_eq!(
+            lines[4],
+            format!("{:>12}  {:<34}.data 3  ; 9 reads, 3 writes", "", "0010  3")
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Goal seeking over patched program images.
+//!
+//! Plenty of puzzles boil down to: poke some values into a program, run it
+//! and check what comes out. `GoalSeek` tries every combination of
+//! candidate values for a set of addresses, spread over all available
+//! cores, and reports the combinations whose probed result satisfies a
+//! target predicate. Patches which make the program fault, or run past the
+//! cycle limit, never match.
+use crate::cpu::Cpu;
+use std::collections::VecDeque;
+use std::ops::{Range, RangeInclusive};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Candidates handed to a worker at a time.
+const CHUNK: usize = 64;
+
+/// What to look at once a patched program halts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// A single memory cell.
+    Memory(usize),
+    /// Everything the program wrote to its output.
+    Output,
+}
+
+pub struct GoalSeek {
+    image: Vec<i64>,
+    patches: Vec<(usize, RangeInclusive<i64>)>,
+    probe: Probe,
+    input: Vec<i64>,
+    threads: usize,
+    budget: Option<u64>,
+}
+
+impl GoalSeek {
+    // By default the search probes the output stream on every core.
+    pub fn new(image: &[i64]) -> GoalSeek {
+        GoalSeek {
+            image: image.to_vec(),
+            patches: Vec::new(),
+            probe: Probe::Output,
+            input: Vec::new(),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            budget: None,
+        }
+    }
+
+    /// Try every value in |values| at |addr|. Later patches vary fastest,
+    /// like the inner loop of a nest.
+    pub fn patch(mut self, addr: usize, values: RangeInclusive<i64>) -> Self {
+        self.patches.push((addr, values));
+        self
+    }
+
+    pub fn probe(mut self, probe: Probe) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Input fed to every run of the program.
+    pub fn with_input(mut self, input: Vec<i64>) -> Self {
+        self.input = input;
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Give up on any run taking more than |cycles| instructions, so that
+    /// patches which send the program into a loop don't hang the search.
+    pub fn with_cycle_limit(mut self, cycles: u64) -> Self {
+        self.budget = Some(cycles);
+        self
+    }
+
+    /// Number of combinations in the search space. This saturates at
+    /// `usize::MAX`, and combinations past that are never tried.
+    pub fn len(&self) -> usize {
+        let len = self
+            .patches
+            .iter()
+            .fold(1u128, |n, (_, values)| n.saturating_mul(width(values)));
+        len.min(usize::MAX as u128) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The values patched in for the |n|th combination.
+    fn assignment(&self, n: usize) -> Vec<i64> {
+        let mut values = vec![0; self.patches.len()];
+        let mut n = n as u128;
+        for (i, (_, range)) in self.patches.iter().enumerate().rev() {
+            let width = width(range);
+            values[i] = (*range.start() as i128 + (n % width) as i128) as i64;
+            n /= width;
+        }
+        values
+    }
+
+    // Run the program with |values| patched in, returning what the probe
+    // saw if it halted cleanly.
+    fn try_values(&self, values: &[i64]) -> Option<Vec<i64>> {
+        let mut image = self.image.clone();
+        for ((addr, _), &value) in self.patches.iter().zip(values) {
+            if *addr >= image.len() {
+                image.resize(addr + 1, 0);
+            }
+            image[*addr] = value;
+        }
+        let mut cpu = Cpu::new(image)
+            .with_input(self.input.iter().copied().collect::<VecDeque<i64>>())
+            .with_output(Vec::new());
+        cpu.set_cycle_limit(self.budget);
+        let cpu = cpu.run().ok()?;
+        Some(match self.probe {
+            Probe::Memory(addr) => vec![cpu.memory[addr]],
+            Probe::Output => cpu.output,
+        })
+    }
+
+    // Have every worker pull chunks of candidates until the space is
+    // exhausted, or until a match is known below everything left when only
+    // the first is wanted. Matches come back in search order.
+    fn search<F>(&self, target: F, first: bool) -> Vec<Vec<i64>>
+    where
+        F: Fn(&[i64]) -> bool + Sync,
+    {
+        let total = self.len();
+        let next = AtomicUsize::new(0);
+        let best = AtomicUsize::new(usize::MAX);
+        let found = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                scope.spawn(|| {
+                    while let Some(chunk) = claim(&next, total) {
+                        if first && chunk.start > best.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        for n in chunk {
+                            let values = self.assignment(n);
+                            if self.try_values(&values).is_some_and(|seen| target(&seen)) {
+                                found.lock().unwrap().push((n, values));
+                                if first {
+                                    best.fetch_min(n, Ordering::Relaxed);
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        let mut found = found.into_inner().unwrap();
+        found.sort();
+        found.into_iter().map(|(_, values)| values).collect()
+    }
+
+    /// Every combination of patched values for which |target| accepts
+    /// what the probe saw, in search order. For a memory probe |target| is
+    /// given a single word.
+    pub fn all<F>(&self, target: F) -> Vec<Vec<i64>>
+    where
+        F: Fn(&[i64]) -> bool + Sync,
+    {
+        self.search(target, false)
+    }
+
+    /// The first matching combination in search order, stopping the
+    /// search as soon as it can be sure of it.
+    pub fn first<F>(&self, target: F) -> Option<Vec<i64>>
+    where
+        F: Fn(&[i64]) -> bool + Sync,
+    {
+        self.search(target, true).into_iter().next()
+    }
+}
+
+// The next chunk of candidates below |total| not yet handed out, moving
+// |next| on past it. The counter stops at |total| rather than wrapping.
+fn claim(next: &AtomicUsize, total: usize) -> Option<Range<usize>> {
+    next.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+        (n < total).then(|| total.min(n.saturating_add(CHUNK)))
+    })
+    .ok()
+    .map(|start| start..total.min(start.saturating_add(CHUNK)))
+}
+
+// Number of values in |range|, which may be more than fit in a word.
+fn width(range: &RangeInclusive<i64>) -> u128 {
+    (*range.end() as i128 - *range.start() as i128 + 1).max(0) as u128
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_probe() {
+        // [0] = #a * #b
+        let seek = GoalSeek::new(&[1102, 0, 0, 0, 99])
+            .patch(1, 1..=6)
+            .patch(2, 1..=6)
+            .probe(Probe::Memory(0));
+        assert_eq!(seek.len(), 36);
+        let found = seek.all(|v| v[0] == 12);
+        assert_eq!(found, vec![vec![2, 6], vec![3, 4], vec![4, 3], vec![6, 2]]);
+        assert_eq!(seek.first(|v| v[0] == 12), Some(vec![2, 6]));
+    }
+
+    #[test]
+    fn output_probe_and_faults() {
+        // out [x]; out [y]; hlt, where most candidate addresses fault
+        let seek = GoalSeek::new(&[4, 0, 4, 0, 99])
+            .patch(1, -5..=4)
+            .patch(3, -5..=4)
+            .with_threads(3);
+        let found = seek.all(|out| out == [99, 4]);
+        assert_eq!(found, vec![vec![4, 0], vec![4, 1], vec![4, 2]]);
+        assert_eq!(seek.first(|out| out == [99, 4]), Some(vec![4, 0]));
+        assert_eq!(seek.first(|out| out.is_empty()), None);
+    }
+
+    #[test]
+    fn cycle_limit() {
+        // jt #c, #0 spins forever unless patched to fall through to out #c
+        let seek = GoalSeek::new(&[1105, 0, 0, 104, 0, 99])
+            .patch(1, 0..=2)
+            .patch(4, 7..=7)
+            .with_cycle_limit(1000);
+        assert_eq!(seek.all(|_| true), vec![vec![0, 7]]);
+    }
+
+    #[test]
+    fn matches_serial_search() {
+        let image = crate::parse(include_str!("../../2/input.txt"));
+        let seek = GoalSeek::new(&image)
+            .patch(1, 0..=99)
+            .patch(2, 0..=99)
+            .probe(Probe::Memory(0));
+        let goal = 19_690_720;
+        let serial: Vec<Vec<i64>> = (0..seek.len())
+            .map(|n| seek.assignment(n))
+            .filter(|v| seek.try_values(v) == Some(vec![goal]))
+            .collect();
+        assert_eq!(seek.all(|v| v[0] == goal), serial);
+        assert_eq!(seek.first(|v| v[0] == goal), Some(vec![60, 86]));
+    }
+
+    #[test]
+    fn huge_ranges() {
+        // [0] = #a + #b
+        let seek = GoalSeek::new(&[1101, 0, 0, 0, 99])
+            .patch(1, i64::MIN..=i64::MAX)
+            .patch(2, 0..=1)
+            .probe(Probe::Memory(0));
+        assert_eq!(seek.len(), usize::MAX);
+        assert_eq!(seek.assignment(0), vec![i64::MIN, 0]);
+        assert_eq!(seek.assignment(usize::MAX), vec![-1, 1]);
+        assert_eq!(
+            seek.first(|v| v[0] == i64::MIN + 1),
+            Some(vec![i64::MIN, 1])
+        );
+
+        // Claiming chunks at the top of the space stops rather than wraps
+        let next = AtomicUsize::new(usize::MAX - CHUNK - 1);
+        assert_eq!(
+            claim(&next, usize::MAX),
+            Some(usize::MAX - CHUNK - 1..usize::MAX - 1)
+        );
+        assert_eq!(claim(&next, usize::MAX), Some(usize::MAX - 1..usize::MAX));
+        assert_eq!(claim(&next, usize::MAX), None);
+        assert_eq!(next.load(Ordering::Relaxed), usize::MAX);
+
+        assert!(GoalSeek::new(&[99])
+            .patch(0, RangeInclusive::new(1, 0))
+            .is_empty());
+    }
+}
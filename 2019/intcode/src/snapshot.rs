@@ -0,0 +1,434 @@
+//! Complete machine state, captured so that it can be restored later.
+//!
+//! A `Snapshot` holds everything needed to rebuild a `Cpu` exactly as it
+//! was: memory, registers and the words still sitting in its input and
+//! output queues. Cloning one only bumps a reference count, so a machine
+//! can be forked at a decision point and each branch restored from the
+//! same snapshot.
+//!
+//! Snapshots are saved as plain text, one field per line:
+//!
+//! ```text
+//! intcode snapshot 1
+//! ip 12
+//! rbase 2000
+//! cycles 4812
+//! limit 65536
+//! arithmetic checked
+//! budget 100000
+//! detect-loops
+//! input 5,-3
+//! output 42
+//! memory 0 1,9,10,3,2,3,11,0,99,30,40,50
+//! memory 1048576 7,0,0,5
+//! wide 1048581 36893488147419103232
+//! ```
+//!
+//! The header line must come first; the other fields may appear in any
+//! order. `limit` is omitted when memory is unbounded, `arithmetic` when
+//! the machine wraps, `budget` when it has no cycle limit and
+//! `detect-loops` when it isn't watching for loops. `input`/`output` are
+//! left empty when their queue is. Each `memory` line stores a run of
+//! consecutive words from the given address; anything not covered by one
+//! reads as 0, so long stretches of zeros are left out. A `wide` line gives the full value of a cell holding one too
+//! big for a word under widened arithmetic.
+use crate::cpu::Arithmetic;
+use crate::memory::Memory;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const HEADER: &str = "intcode snapshot 1";
+
+// Zeros it takes to end a `memory` line; shorter gaps are written out.
+const GAP: usize = 16;
+
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub ip: usize,
+    pub rbase: i64,
+    pub cycles: u64,
+    pub arithmetic: Arithmetic,
+    /// Cycle limit the machine was given, counted from boot like `cycles`.
+    pub budget: Option<u64>,
+    pub detect_loops: bool,
+    pub memory: Arc<Memory>,
+    /// Input the machine had yet to read.
+    pub input: Vec<i64>,
+    /// Output the machine had produced but nobody had collected.
+    pub output: Vec<i64>,
+}
+
+fn words(s: &str) -> Result<Vec<i64>, String> {
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+    s.split(',')
+        .map(|w| w.trim().parse().map_err(|_| format!("bad word {:?}", w)))
+        .collect()
+}
+
+fn join(words: &[i64]) -> String {
+    let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+    words.join(",")
+}
+
+// A queue's words after its field name, or nothing at all if it is empty.
+fn list(words: &[i64]) -> String {
+    match words {
+        [] => String::new(),
+        _ => format!(" {}", join(words)),
+    }
+}
+
+// The stretches of |words| worth writing, with their offsets. Each starts
+// and ends on a non-zero word, and none holds GAP zeros in a row.
+fn runs(words: &[i64]) -> Vec<(usize, &[i64])> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while let Some(start) = words[i..].iter().position(|&w| w != 0) {
+        let start = i + start;
+        let mut end = start + 1;
+        let mut j = end;
+        while j < words.len() && j - end < GAP {
+            if words[j] != 0 {
+                end = j + 1;
+            }
+            j += 1;
+        }
+        runs.push((start, &words[start..end]));
+        i = end;
+    }
+    runs
+}
+
+fn invalid(line: usize, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+// The value of the field |key| on line |n|, parsed as whatever type the
+// field holds.
+fn number<T: FromStr>(n: usize, key: &str, value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(n, format!("bad {} {:?}", key, value)))
+}
+
+impl Snapshot {
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "ip {}", self.ip)?;
+        writeln!(out, "rbase {}", self.rbase)?;
+        writeln!(out, "cycles {}", self.cycles)?;
+        if let Some(limit) = self.memory.limit() {
+            writeln!(out, "limit {}", limit)?;
+        }
+        match self.arithmetic {
+            Arithmetic::Wrapping => {}
+            Arithmetic::Checked => writeln!(out, "arithmetic checked")?,
+            Arithmetic::Widened => writeln!(out, "arithmetic widened")?,
+        }
+        if let Some(budget) = self.budget {
+            writeln!(out, "budget {}", budget)?;
+        }
+        if self.detect_loops {
+            writeln!(out, "detect-loops")?;
+        }
+        writeln!(out, "input{}", list(&self.input))?;
+        writeln!(out, "output{}", list(&self.output))?;
+        for (addr, words) in self.memory.chunks() {
+            for (start, run) in runs(words) {
+                writeln!(out, "memory {} {}", addr + start, join(run))?;
+            }
+        }
+        for (addr, value) in self.memory.wide_cells() {
+            writeln!(out, "wide {} {}", addr, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: BufRead>(input: R) -> io::Result<Snapshot> {
+        let mut lines = input.lines();
+        let header = lines.next().transpose()?;
+        if header.as_deref().map(str::trim) != Some(HEADER) {
+            return Err(invalid(1, format!("expected {:?}", HEADER)));
+        }
+        let mut snapshot = Snapshot::default();
+        let mut memory = Memory::new();
+        // Stored once the whole file is read, so that a limit given after
+        // them still applies.
+        let mut runs = Vec::new();
+        // Applied last, as writing the cell's low word would clear them.
+        let mut wide = Vec::new();
+        for (n, line) in lines.enumerate() {
+            let n = n + 2;
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = match line.find(' ') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => (line, ""),
+            };
+            match key {
+                "ip" => snapshot.ip = number(n, key, value)?,
+                "rbase" => snapshot.rbase = number(n, key, value)?,
+                "cycles" => snapshot.cycles = number(n, key, value)?,
+                "limit" => memory.set_limit(Some(number(n, key, value)?)),
+                "arithmetic" => {
+                    snapshot.arithmetic = match value {
+                        "wrapping" => Arithmetic::Wrapping,
+                        "checked" => Arithmetic::Checked,
+                        "widened" => Arithmetic::Widened,
+                        _ => return Err(invalid(n, format!("bad arithmetic {:?}", value))),
+                    }
+                }
+                "budget" => snapshot.budget = Some(number(n, key, value)?),
+                "detect-loops" if value.is_empty() => snapshot.detect_loops = true,
+                "detect-loops" => return Err(invalid(n, format!("bad {} {:?}", key, value))),
+                "input" => snapshot.input = words(value).map_err(|e| invalid(n, e))?,
+                "output" => snapshot.output = words(value).map_err(|e| invalid(n, e))?,
+                "memory" => {
+                    let (addr, run) = match value.find(' ') {
+                        Some(i) => (&value[..i], &value[i + 1..]),
+                        None => (value, ""),
+                    };
+                    let addr: usize = number(n, key, addr)?;
+                    let run = words(run).map_err(|e| invalid(n, e))?;
+                    runs.push((n, addr, run));
+                }
+                "wide" => {
+                    let cell = value.split_once(' ').and_then(|(addr, value)| {
+                        Some((addr.parse::<usize>().ok()?, value.parse::<i128>().ok()?))
+                    });
+                    match cell {
+                        Some((addr, value)) => wide.push((n, addr, value)),
+                        None => return Err(invalid(n, format!("bad wide {:?}", value))),
+                    }
+                }
+                _ => return Err(invalid(n, format!("unknown field {:?}", key))),
+            }
+        }
+        for (n, addr, run) in runs {
+            for (i, &word) in run.iter().enumerate() {
+                let addr = addr
+                    .checked_add(i)
+                    .ok_or_else(|| invalid(n, format!("memory run past {}", usize::MAX)))?;
+                memory
+                    .set(addr, word)
+                    .map_err(|e| invalid(n, e.to_string()))?;
+            }
+        }
+        for (n, addr, value) in wide {
+            memory
+                .set_wide(addr, value)
+                .map_err(|e| invalid(n, e.to_string()))?;
+        }
+        snapshot.memory = Arc::new(memory);
+        Ok(snapshot)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write(io::BufWriter::new(std::fs::File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        Snapshot::read(io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{Cpu, Status};
+    use crate::testing::doubler;
+    use std::collections::VecDeque;
+
+    type Machine = Cpu<VecDeque<i64>, Vec<i64>>;
+
+    // The doubler with nothing queued for it yet.
+    fn machine() -> Machine {
+        Cpu::new(doubler())
+            .with_input(VecDeque::new())
+            .with_output(Vec::new())
+    }
+
+    #[test]
+    fn fork_and_restore() {
+        let mut cpu = machine();
+        cpu.push_input(21);
+        cpu.push_input(4);
+        assert_eq!(cpu.resume(), Ok(Status::Output(42)));
+        cpu.output.push(42);
+        let snapshot = cpu.snapshot();
+
+        // Run the original on, then check the fork picks up where it was
+        assert_eq!(cpu.resume(), Ok(Status::Output(8)));
+        let mut fork = Machine::restore(&snapshot.clone());
+        assert_eq!(fork.ip(), snapshot.ip);
+        assert_eq!(fork.cycles(), snapshot.cycles);
+        assert_eq!(fork.input, VecDeque::from(vec![4]));
+        assert_eq!(fork.output, vec![42]);
+        assert_eq!(fork.resume(), Ok(Status::Output(8)));
+        fork.push_input(0);
+        assert_eq!(fork.resume(), Ok(Status::Halted));
+
+        let mut clone = cpu.clone();
+        clone.push_input(0);
+        assert_eq!(clone.resume(), Ok(Status::Halted));
+        assert_eq!(cpu.resume(), Ok(Status::NeedsInput));
+    }
+
+    #[test]
+    fn text_format() {
+        let mut cpu = machine().with_memory_limit(1 << 30);
+        cpu.memory[2_000_000] = 7;
+        cpu.memory.set_wide(2_000_001, 1 << 65).unwrap();
+        cpu.push_input(5);
+        cpu.push_input(-3);
+        assert_eq!(cpu.resume(), Ok(Status::Output(10)));
+
+        let mut text = Vec::new();
+        cpu.snapshot().write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(
+            text,
+            "intcode snapshot 1
+ip 11
+rbase 0
+cycles 4
+limit 1073741824
+input -3
+output
+memory 0 3,15,1006,15,14,1002,15,2,15,4,15,1105,1,0,99,10
+memory 2000000 7
+wide 2000001 36893488147419103232
+"
+        );
+
+        let restored = Machine::restore(&Snapshot::read(text.as_bytes()).unwrap());
+        assert_eq!(restored.memory.limit(), Some(1 << 30));
+        assert_eq!(restored.memory[2_000_000], 7);
+        assert_eq!(restored.memory.get_wide(2_000_001), 1 << 65);
+        let mut again = Vec::new();
+        restored.snapshot().write(&mut again).unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), text);
+    }
+
+    #[test]
+    fn zero_gaps() {
+        let mut words = vec![0; 40];
+        words[1] = 5;
+        words[3] = 6;
+        words[20] = 7;
+        words[37] = 8;
+        words[39] = 9;
+        assert_eq!(
+            runs(&words),
+            vec![
+                (1, &words[1..4]),
+                (20, &words[20..21]),
+                (37, &words[37..40]),
+            ]
+        );
+        assert!(runs(&[0; 40]).is_empty());
+
+        let mut cpu = machine();
+        cpu.memory[100] = 1;
+        cpu.memory[1 << 20] = -1;
+        let mut text = Vec::new();
+        cpu.snapshot().write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.ends_with("\nmemory 100 1\nmemory 1048576 -1\n"));
+        let restored = Machine::restore(&Snapshot::read(text.as_bytes()).unwrap());
+        assert!(restored.memory.same_contents(&cpu.memory));
+    }
+
+    #[test]
+    fn arithmetic_policy() {
+        // [0] = i64::MAX + 1, which only a wrapping machine gets past
+        let program = vec![1101, i64::MAX, 1, 0, 99];
+        for &policy in [Arithmetic::Checked, Arithmetic::Widened].iter() {
+            let cpu = Cpu::new(program.clone())
+                .with_arithmetic(policy)
+                .with_input(VecDeque::new())
+                .with_output(Vec::new());
+            let mut text = Vec::new();
+            cpu.snapshot().write(&mut text).unwrap();
+            let mut restored = Machine::restore(&Snapshot::read(&text[..]).unwrap());
+            assert_eq!(restored.arithmetic, policy);
+            let halted = restored.resume();
+            assert_eq!(halted.is_ok(), policy == Arithmetic::Widened);
+        }
+        let err = Snapshot::read(&b"intcode snapshot 1\narithmetic saturating\n"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 2: bad arithmetic \"saturating\"");
+    }
+
+    #[test]
+    fn budget_and_loop_detection() {
+        // jmp #0, spinning in place
+        let cpu = Cpu::new(vec![1105, 1, 0])
+            .with_cycle_limit(100)
+            .with_loop_detection()
+            .with_input(VecDeque::new())
+            .with_output(Vec::new());
+        let mut text = Vec::new();
+        cpu.snapshot().write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("\nbudget 100\ndetect-loops\n"));
+        let mut restored = Machine::restore(&Snapshot::read(text.as_bytes()).unwrap());
+        assert_eq!((restored.budget, restored.detect_loops), (Some(100), true));
+        let err = restored.resume().err().unwrap();
+        assert_eq!(err.kind, crate::ErrorKind::InfiniteLoop);
+
+        let mut unlimited = Vec::new();
+        machine().snapshot().write(&mut unlimited).unwrap();
+        let restored = Machine::restore(&Snapshot::read(&unlimited[..]).unwrap());
+        assert_eq!((restored.budget, restored.detect_loops), (None, false));
+    }
+
+    #[test]
+    fn bad_input() {
+        let err = Snapshot::read(&b"intcode snapshot 2\n"[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = Snapshot::read(&b"intcode snapshot 1\nip x\n"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 2: bad ip \"x\"");
+        let err = Snapshot::read(&b"intcode snapshot 1\n\nmemory 0 1,,2\n"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 3: bad word \"\"");
+
+        // Fields that can't be negative mustn't wrap round when they are
+        for (text, message) in [
+            ("ip -1", "line 2: bad ip \"-1\""),
+            ("cycles -5", "line 2: bad cycles \"-5\""),
+            ("limit -1", "line 2: bad limit \"-1\""),
+            ("memory -1 1,2", "line 2: bad memory \"-1\""),
+            (
+                "ip 18446744073709551616",
+                "line 2: bad ip \"18446744073709551616\"",
+            ),
+        ] {
+            let text = format!("intcode snapshot 1\n{}\n", text);
+            let err = Snapshot::read(text.as_bytes()).err().unwrap();
+            assert_eq!(err.to_string(), message);
+        }
+        let text = format!("intcode snapshot 1\nmemory {} 1,2\n", usize::MAX);
+        let err = Snapshot::read(text.as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // The limit holds for memory however early in the file it comes
+        let text = "intcode snapshot 1\nmemory 8 1,2\nlimit 9\n";
+        let err = Snapshot::read(text.as_bytes()).err().unwrap();
+        assert!(err.to_string().starts_with("line 2: "), "{}", err);
+    }
+}
@@ -0,0 +1,33 @@
+// Fixtures shared by the unit tests of several modules.
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+// Double each input until a 0 is read
+// 0: in [15]  2: jf [15] #14  5: mul [15] #2 -> [15]
+// 9: out [15]  11: jt #1 #0   14: hlt
+pub fn doubler() -> Vec<i64> {
+    vec![
+        3, 15, 1006, 15, 14, 1002, 15, 2, 15, 4, 15, 1105, 1, 0, 99, 0,
+    ]
+}
+
+// Output buffer which can still be read once a Cpu owns the writer, as a
+// tracer or transcript recorder does.
+#[derive(Clone, Default)]
+pub struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Shared {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
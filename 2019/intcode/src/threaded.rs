@@ -0,0 +1,352 @@
+//! Ahead of time translation of Intcode images into threaded code.
+//!
+//! `Program::compile` decodes every address of an image once and turns each
+//! instruction into a closure specialised for its opcode and parameter
+//! modes, so running it skips decoding entirely. A compiled program runs on
+//! an ordinary `Cpu` and can be reused for any number of machines started
+//! from the same image.
+//!
+//! Programs are free to rewrite their own code, so before running a
+//! compiled instruction the words it was compiled from are checked against
+//! memory. Any instruction which no longer matches, along with I/O, `HALT`
+//! and anything that didn't decode, falls back to the interpreter. Machines
+//! with a tracer attached, loop detection on or using widened arithmetic are
+//! interpreted throughout.
+use crate::cpu::{Arithmetic, Cpu, Instruction, Parameter, Status};
+use crate::error::{ErrorKind, VmError};
+use crate::io::{Input, Output};
+use crate::memory::Memory;
+
+// What a compiled instruction did, for the executor to move the ip on.
+enum Effect {
+    Next,
+    Jump(usize),
+    Wrote(usize),
+}
+
+// A compiled instruction runs against memory and the relative base, and is
+// told whether arithmetic is checked.
+type Op = Box<dyn Fn(&mut Memory, &mut i64, bool) -> Result<Effect, ErrorKind> + Send + Sync>;
+
+struct Slot {
+    // The words the instruction was compiled from.
+    words: [i64; 4],
+    width: usize,
+    op: Op,
+}
+
+impl Slot {
+    // Whether memory at |ip| still holds the compiled instruction.
+    fn current(&self, memory: &Memory, ip: usize) -> bool {
+        self.words[..self.width]
+            .iter()
+            .enumerate()
+            .all(|(k, &word)| memory.get(ip + k) == word)
+    }
+}
+
+// How an operand of each parameter mode is fetched, resolved when the
+// instruction is compiled rather than each time it runs.
+trait Mode {
+    fn load(memory: &Memory, rbase: i64, x: i64) -> Result<i64, ErrorKind>;
+    fn dest(memory: &Memory, rbase: i64, x: i64) -> Result<usize, ErrorKind>;
+}
+
+struct Position;
+struct Immediate;
+struct Relative;
+
+impl Mode for Position {
+    fn load(memory: &Memory, rbase: i64, x: i64) -> Result<i64, ErrorKind> {
+        Ok(memory.get(Self::dest(memory, rbase, x)?))
+    }
+
+    fn dest(memory: &Memory, _: i64, x: i64) -> Result<usize, ErrorKind> {
+        memory.address(x).map_err(ErrorKind::Memory)
+    }
+}
+
+impl Mode for Immediate {
+    fn load(_: &Memory, _: i64, x: i64) -> Result<i64, ErrorKind> {
+        Ok(x)
+    }
+
+    fn dest(_: &Memory, _: i64, _: i64) -> Result<usize, ErrorKind> {
+        Err(ErrorKind::InvalidDestination)
+    }
+}
+
+impl Mode for Relative {
+    fn load(memory: &Memory, rbase: i64, x: i64) -> Result<i64, ErrorKind> {
+        Ok(memory.get(Self::dest(memory, rbase, x)?))
+    }
+
+    fn dest(memory: &Memory, rbase: i64, x: i64) -> Result<usize, ErrorKind> {
+        let addr = rbase.checked_add(x).ok_or(ErrorKind::Overflow)?;
+        memory.address(addr).map_err(ErrorKind::Memory)
+    }
+}
+
+// The operations of the three operand instructions. None means overflow.
+trait Operator {
+    fn apply(a: i64, b: i64, checked: bool) -> Option<i64>;
+}
+
+struct Add;
+struct Mul;
+struct LessThan;
+struct Equals;
+
+impl Operator for Add {
+    fn apply(a: i64, b: i64, checked: bool) -> Option<i64> {
+        if checked {
+            a.checked_add(b)
+        } else {
+            Some(a.wrapping_add(b))
+        }
+    }
+}
+
+impl Operator for Mul {
+    fn apply(a: i64, b: i64, checked: bool) -> Option<i64> {
+        if checked {
+            a.checked_mul(b)
+        } else {
+            Some(a.wrapping_mul(b))
+        }
+    }
+}
+
+impl Operator for LessThan {
+    fn apply(a: i64, b: i64, _: bool) -> Option<i64> {
+        Some((a < b) as i64)
+    }
+}
+
+impl Operator for Equals {
+    fn apply(a: i64, b: i64, _: bool) -> Option<i64> {
+        Some((a == b) as i64)
+    }
+}
+
+// Faults come in the same order as the interpreter's: operands, then the
+// operation, then the destination.
+fn binary<F: Operator, A: Mode, B: Mode, D: Mode>(a: i64, b: i64, d: i64) -> Op {
+    Box::new(move |memory, rbase, checked| {
+        let value = F::apply(
+            A::load(memory, *rbase, a)?,
+            B::load(memory, *rbase, b)?,
+            checked,
+        )
+        .ok_or(ErrorKind::Overflow)?;
+        let addr = D::dest(memory, *rbase, d)?;
+        memory.set(addr, value).map_err(ErrorKind::Memory)?;
+        Ok(Effect::Wrote(addr))
+    })
+}
+
+fn jump<C: Mode, T: Mode>(test: bool, cond: i64, target: i64) -> Op {
+    Box::new(move |memory, rbase, _| {
+        if (C::load(memory, *rbase, cond)? != 0) != test {
+            return Ok(Effect::Next);
+        }
+        let target = T::load(memory, *rbase, target)?;
+        let addr = memory.address(target).map_err(ErrorKind::Memory)?;
+        Ok(Effect::Jump(addr))
+    })
+}
+
+fn relbase<A: Mode>(offset: i64) -> Op {
+    Box::new(move |memory, rbase, _| {
+        let offset = A::load(memory, *rbase, offset)?;
+        *rbase = rbase.checked_add(offset).ok_or(ErrorKind::Overflow)?;
+        Ok(Effect::Next)
+    })
+}
+
+// Evaluate |body| with |M| naming the mode type of parameter |p| and |x|
+// bound to its value.
+macro_rules! with_mode {
+    ($p:expr, $m:ident, $x:ident => $body:expr) => {
+        match $p {
+            Parameter::Position($x) => {
+                type $m = Position;
+                $body
+            }
+            Parameter::Immediate($x) => {
+                type $m = Immediate;
+                $body
+            }
+            Parameter::Relative($x) => {
+                type $m = Relative;
+                $body
+            }
+        }
+    };
+}
+
+fn three<F: Operator>(args: [Parameter; 3]) -> Op {
+    with_mode!(args[0], A, a => with_mode!(args[1], B, b => with_mode!(args[2], D, d =>
+        binary::<F, A, B, D>(a, b, d)
+    )))
+}
+
+// The closure for |instruction|, or None if it is left to the interpreter.
+fn translate(instruction: Instruction) -> Option<Op> {
+    Some(match instruction {
+        Instruction::ADD(args) => three::<Add>(args),
+        Instruction::MUL(args) => three::<Mul>(args),
+        Instruction::LESSTHAN(args) => three::<LessThan>(args),
+        Instruction::EQUALS(args) => three::<Equals>(args),
+        Instruction::JUMP(test, [cond, target]) => {
+            with_mode!(cond, C, c => with_mode!(target, T, t => jump::<C, T>(test, c, t)))
+        }
+        Instruction::RELBASE([offset]) => with_mode!(offset, A, a => relbase::<A>(a)),
+        Instruction::INPUT(_) | Instruction::OUTPUT(_) | Instruction::HALT => return None,
+    })
+}
+
+pub struct Program {
+    // The compiled instruction at each address of the image, if any.
+    slots: Vec<Option<Slot>>,
+}
+
+impl Program {
+    pub fn compile(image: &[i64]) -> Program {
+        let slots = (0..image.len())
+            .map(|addr| {
+                let instruction = Instruction::decode(image, addr).ok()?;
+                let width = instruction.width();
+                let mut words = [0; 4];
+                words[..width].copy_from_slice(&image[addr..addr + width]);
+                Some(Slot {
+                    words,
+                    width,
+                    op: translate(instruction)?,
+                })
+            })
+            .collect();
+        Program { slots }
+    }
+
+    /// Like `Cpu::resume`, running compiled code wherever it can.
+    pub fn resume<I: Input, O: Output>(&self, cpu: &mut Cpu<I, O>) -> Result<Status, VmError> {
+        if cpu.tracing()
+            || cpu.detect_loops
+            || cpu.arithmetic == Arithmetic::Widened
+            || cpu.memory.has_wide()
+        {
+            return cpu.resume();
+        }
+        let checked = cpu.arithmetic == Arithmetic::Checked;
+        // The interpreter raises the fault once the budget runs out
+        let budget = cpu.budget.unwrap_or(u64::MAX);
+        // The interpreter faults on an ip beyond the memory limit
+        let end = cpu.memory.limit().unwrap_or(usize::MAX);
+        let slots = &self.slots[..self.slots.len().min(end)];
+        loop {
+            let ip = cpu.ip;
+            let slot = match slots.get(ip) {
+                Some(Some(slot)) if cpu.cycles < budget && slot.current(&cpu.memory, ip) => slot,
+                _ => match cpu.step()? {
+                    Status::Running => continue,
+                    status => return Ok(status),
+                },
+            };
+            cpu.current = ip;
+            cpu.last_write = None;
+            match (slot.op)(&mut cpu.memory, &mut cpu.rbase, checked) {
+                Ok(Effect::Next) => cpu.ip = ip + slot.width,
+                Ok(Effect::Jump(target)) => cpu.ip = target,
+                Ok(Effect::Wrote(addr)) => {
+                    cpu.ip = ip + slot.width;
+                    cpu.last_write = Some(addr);
+                }
+                Err(kind) => return Err(cpu.fault(kind)),
+            }
+            cpu.cycles += 1;
+        }
+    }
+
+    /// Like `Cpu::run`, running compiled code wherever it can.
+    pub fn run<I: Input, O: Output>(&self, mut cpu: Cpu<I, O>) -> Result<Cpu<I, O>, VmError> {
+        loop {
+            match self.resume(&mut cpu)? {
+                Status::Output(value) => cpu.output.write(value),
+                Status::Halted => break,
+                Status::NeedsInput => {
+                    cpu.current = cpu.ip;
+                    return Err(cpu.fault(ErrorKind::InputExhausted));
+                }
+                Status::Running => unreachable!(),
+            }
+        }
+        Ok(cpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn self_modifying_code() {
+        // add #1, #1, [4] rewrites the opcode of the compiled add after it
+        // so that it runs as mul [0], [0], [0] instead
+        let image = vec![1101, 1, 1, 4, 1, 0, 0, 0, 99, 0];
+        let program = Program::compile(&image);
+        let compiled = program.run(Cpu::new(image.clone())).unwrap();
+        let interpreted = Cpu::new(image).run().unwrap();
+        assert_eq!(
+            compiled.memory.slice(0, 10),
+            interpreted.memory.slice(0, 10)
+        );
+        assert_eq!(compiled.memory[0], 1101 * 1101);
+        assert_eq!(compiled.cycles(), interpreted.cycles());
+    }
+
+    #[test]
+    fn reused_across_machines() {
+        let image = crate::parse(include_str!("../../5/input.txt"));
+        let program = Program::compile(&image);
+        for input in [1, 5] {
+            let run = |cpu: Cpu| {
+                cpu.with_input(VecDeque::from(vec![input]))
+                    .with_output(vec![])
+            };
+            let compiled = program.run(run(Cpu::new(image.clone()))).unwrap();
+            let interpreted = run(Cpu::new(image.clone())).run().unwrap();
+            assert_eq!(compiled.output, interpreted.output);
+            assert_eq!(compiled.cycles(), interpreted.cycles());
+        }
+    }
+
+    #[test]
+    fn faults_and_policies() {
+        // mul [0], #big, [0] under each policy, then an immediate store
+        let image = vec![1002, 0, 1 << 62, 0, 1101, 0, 0, 0, 99];
+        let program = Program::compile(&image);
+        for &arithmetic in [Arithmetic::Wrapping, Arithmetic::Checked].iter() {
+            let compiled = program.run(Cpu::new(image.clone()).with_arithmetic(arithmetic));
+            let interpreted = Cpu::new(image.clone()).with_arithmetic(arithmetic).run();
+            match (compiled, interpreted) {
+                (Ok(a), Ok(b)) => assert_eq!(a.memory[0], b.memory[0]),
+                (a, b) => assert_eq!(a.err(), b.err()),
+            }
+        }
+        // jt #1, #4 past a memory limit of 4
+        let image = vec![1105, 1, 4, 0, 99];
+        let limited = || Cpu::new(image.clone()).with_memory_limit(4);
+        let err = Program::compile(&image).run(limited()).err();
+        assert_eq!(err, limited().run().err());
+        assert_eq!(err.unwrap().ip, 0);
+
+        // jt #1, #0 under a cycle budget
+        let image = vec![1105, 1, 0];
+        let limited = || Cpu::new(image.clone()).with_cycle_limit(100);
+        let err = Program::compile(&image).run(limited()).err();
+        assert_eq!(err, limited().run().err());
+        assert_eq!(err.unwrap().kind, ErrorKind::CycleLimit);
+    }
+}
@@ -0,0 +1,230 @@
+//! Opt-in, per-instruction execution tracing.
+//!
+//! A `Cpu` built with `with_tracer` hands a `Record` to its tracer after
+//! every instruction it executes. `Trace` writes records to any `Write` as
+//! human readable text or as JSON Lines, `Log` forwards them to the `log`
+//! crate at trace level and a channel `Sender` collects them for code.
+use crate::cpu::Instruction;
+use std::fmt;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Instructions executed before this one.
+    pub cycle: u64,
+    pub ip: usize,
+    /// The raw opcode word, parameter modes included.
+    pub word: i64,
+    pub instruction: Instruction,
+    /// Values of the operands the instruction read, in order. `None` is a
+    /// value too big for a word, read under widened arithmetic.
+    pub reads: Vec<Option<i64>>,
+    /// Memory addresses those values were loaded from, skipping immediate
+    /// operands.
+    pub loads: Vec<usize>,
+    /// Address and value of the memory write, if there was one.
+    pub write: Option<(usize, i64)>,
+    /// The new relative base, if the instruction changed it.
+    pub rbase: Option<i64>,
+}
+
+// A read operand's value, or |missing| if it didn't fit in a word.
+fn word(read: Option<i64>, missing: &str) -> String {
+    read.map_or_else(|| missing.to_string(), |r| r.to_string())
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reads: Vec<String> = self.reads.iter().map(|r| word(*r, "?")).collect();
+        write!(
+            f,
+            "{:6}  {:04}  {:6}  {:<32} ; {}",
+            self.cycle,
+            self.ip,
+            self.word,
+            self.instruction.to_string(),
+            reads.join(" ")
+        )?;
+        if let Some((addr, value)) = self.write {
+            write!(f, " -> [{}] = {}", addr, value)?;
+        }
+        if let Some(rbase) = self.rbase {
+            write!(f, " -> rb = {}", rbase)?;
+        }
+        Ok(())
+    }
+}
+
+impl Record {
+    /// The record as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        let reads: Vec<String> = self.reads.iter().map(|r| word(*r, "null")).collect();
+        let loads: Vec<String> = self.loads.iter().map(|l| l.to_string()).collect();
+        let write = match self.write {
+            Some((addr, value)) => format!("{{\"addr\":{},\"value\":{}}}", addr, value),
+            None => "null".to_string(),
+        };
+        let rbase = match self.rbase {
+            Some(rbase) => rbase.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"cycle\":{},\"ip\":{},\"word\":{},\"op\":\"{}\",\"instruction\":\"{}\",\"reads\":[{}],\"loads\":[{}],\"write\":{},\"rbase\":{}}}",
+            self.cycle,
+            self.ip,
+            self.word,
+            self.instruction.mnemonic(),
+            self.instruction,
+            reads.join(","),
+            loads.join(","),
+            write,
+            rbase
+        )
+    }
+}
+
+pub trait Tracer {
+    fn record(&mut self, record: &Record);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    JsonLines,
+}
+
+/// Write each record as a line of text or JSON.
+pub struct Trace<W> {
+    format: Format,
+    out: W,
+}
+
+impl<W: Write> Trace<W> {
+    pub fn new(format: Format, out: W) -> Trace<W> {
+        Trace { format, out }
+    }
+}
+
+impl<W: Write> Tracer for Trace<W> {
+    fn record(&mut self, record: &Record) {
+        let line = match self.format {
+            Format::Text => record.to_string(),
+            Format::JsonLines => record.to_json(),
+        };
+        writeln!(self.out, "{}", line).unwrap();
+    }
+}
+
+/// Forward each record to `log::trace!` as text.
+pub struct Log;
+
+impl Tracer for Log {
+    fn record(&mut self, record: &Record) {
+        log::trace!("{}", record);
+    }
+}
+
+// Lets a tracer be inspected while a Cpu holds onto it.
+impl<T: Tracer> Tracer for Arc<Mutex<T>> {
+    fn record(&mut self, record: &Record) {
+        self.lock().unwrap().record(record);
+    }
+}
+
+impl Tracer for Sender<Record> {
+    fn record(&mut self, record: &Record) {
+        // Nobody listening any more is no reason to stop the machine.
+        let _ = self.send(record.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+    use crate::testing::Shared;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn records() {
+        let (tx, rx) = channel();
+        Cpu::new(vec![109, 7, 21101, 2, 3, 0, 99])
+            .with_tracer(tx)
+            .run()
+            .unwrap();
+        let records: Vec<Record> = rx.iter().collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].rbase, Some(7));
+        assert_eq!(records[0].reads, vec![Some(7)]);
+        assert_eq!(
+            records[1],
+            Record {
+                cycle: 1,
+                ip: 2,
+                word: 21101,
+                instruction: Instruction::decode(&[21101, 2, 3, 0][..], 0).unwrap(),
+                reads: vec![Some(2), Some(3)],
+                loads: vec![],
+                write: Some((7, 5)),
+                rbase: None,
+            }
+        );
+        assert_eq!(records[2].instruction, Instruction::HALT);
+    }
+
+    #[test]
+    fn text_and_json() {
+        let buf = Shared::default();
+        Cpu::new(vec![1101, 2, 3, 5, 99])
+            .with_tracer(Trace::new(Format::JsonLines, buf.clone()))
+            .run()
+            .unwrap();
+        let text = String::from_utf8(buf.contents()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "{\"cycle\":0,\"ip\":0,\"word\":1101,\"op\":\"add\",\"instruction\":\"add #2, #3, [5]\",\
+             \"reads\":[2,3],\"loads\":[],\"write\":{\"addr\":5,\"value\":5},\"rbase\":null}"
+        );
+
+        let buf = Shared::default();
+        Cpu::new(vec![1101, 2, 3, 5, 99])
+            .with_tracer(Trace::new(Format::Text, buf.clone()))
+            .run()
+            .unwrap();
+        let text = String::from_utf8(buf.contents()).unwrap();
+        assert_eq!(
+            text.lines().next().unwrap(),
+            format!(
+                "     0  0000    1101  {:<32} ; 2 3 -> [5] = 5",
+                "add #2, #3, [5]"
+            )
+        );
+    }
+
+    #[test]
+    fn wide_operands() {
+        // [9] = i64::MAX * 4, then [10] = [9] < 0, which fits a word again
+        let (tx, rx) = channel();
+        Cpu::new(vec![1102, i64::MAX, 4, 9, 1007, 9, 0, 10, 99])
+            .with_arithmetic(crate::Arithmetic::Widened)
+            .with_tracer(tx)
+            .run()
+            .unwrap();
+        let records: Vec<Record> = rx.iter().collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].reads, vec![None, Some(0)]);
+        assert_eq!(records[1].loads, vec![9]);
+        assert_eq!(records[1].write, Some((10, 0)));
+        assert!(records[1].to_string().ends_with("; ? 0 -> [10] = 0"));
+        assert!(records[1].to_json().contains("\"reads\":[null,0]"));
+    }
+
+    #[test]
+    fn off_by_default() {
+        let cpu = Cpu::new(vec![1101, 2, 3, 5, 99]);
+        assert!(!cpu.tracing());
+    }
+}
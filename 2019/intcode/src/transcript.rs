@@ -0,0 +1,340 @@
+//! Recording and replaying a program's I/O.
+//!
+//! A `Recorder` is a tracer which writes every word a program reads or
+//! outputs to a transcript as it happens, along with the cycle it happened
+//! at. Replaying a transcript feeds the recorded input back to the program
+//! and checks it produces the same output at the same cycles, so that a
+//! good interactive session can be kept as a regression test.
+//!
+//! Transcripts are plain text, one event per line after a header:
+//!
+//! ```text
+//! intcode transcript 1
+//! 0 in 21
+//! 3 out 42
+//! 5 in 0
+//! ```
+use crate::cpu::{Cpu, Instruction, Status};
+use crate::error::VmError;
+use crate::trace::{Record, Tracer};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const HEADER: &str = "intcode transcript 1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Input(i64),
+    Output(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Instructions executed before the one doing the I/O.
+    pub cycle: u64,
+    pub event: Event,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.event {
+            Event::Input(word) => write!(f, "{} in {}", self.cycle, word),
+            Event::Output(word) => write!(f, "{} out {}", self.cycle, word),
+        }
+    }
+}
+
+impl Entry {
+    // The I/O a traced instruction did, if any.
+    fn from_record(record: &Record) -> Option<Entry> {
+        let event = match record.instruction {
+            Instruction::INPUT(_) => Event::Input(record.write?.1),
+            Instruction::OUTPUT(_) => Event::Output(record.reads[0]?),
+            _ => return None,
+        };
+        Some(Entry {
+            cycle: record.cycle,
+            event,
+        })
+    }
+}
+
+/// Write each I/O event to |W| as a transcript line, flushing as it goes
+/// so that a session cut short still leaves a usable transcript.
+pub struct Recorder<W> {
+    out: W,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut out: W) -> io::Result<Recorder<W>> {
+        writeln!(out, "{}", HEADER)?;
+        out.flush()?;
+        Ok(Recorder { out })
+    }
+}
+
+impl<W: Write> Tracer for Recorder<W> {
+    fn record(&mut self, record: &Record) {
+        if let Some(entry) = Entry::from_record(record) {
+            writeln!(self.out, "{}", entry).unwrap();
+            self.out.flush().unwrap();
+        }
+    }
+}
+
+/// Why a replay failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The program faulted.
+    Fault(VmError),
+    /// Entry |index| of the run didn't match the transcript. None on either
+    /// side means it had no more entries: the transcript ended, or the
+    /// program halted or wanted input which was never recorded.
+    Diverged {
+        index: usize,
+        expected: Option<Entry>,
+        actual: Option<Entry>,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |entry: &Option<Entry>| match entry {
+            Some(entry) => entry.to_string(),
+            None => "nothing".to_string(),
+        };
+        match self {
+            ReplayError::Fault(e) => write!(f, "{}", e),
+            ReplayError::Diverged {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "diverged at entry {}: expected {}, got {}",
+                index,
+                show(expected),
+                show(actual)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+fn invalid(line: usize, message: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+fn parse_entry(cycle: &str, kind: &str, word: &str) -> Option<Entry> {
+    let word = word.parse().ok()?;
+    let event = match kind {
+        "in" => Event::Input(word),
+        "out" => Event::Output(word),
+        _ => return None,
+    };
+    Some(Entry {
+        cycle: cycle.parse().ok()?,
+        event,
+    })
+}
+
+/// The I/O of a run, in the order it happened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub entries: Vec<Entry>,
+}
+
+impl Tracer for Transcript {
+    fn record(&mut self, record: &Record) {
+        self.entries.extend(Entry::from_record(record));
+    }
+}
+
+impl Transcript {
+    pub fn new() -> Transcript {
+        Transcript::default()
+    }
+
+    /// The words the program read, in order.
+    pub fn inputs(&self) -> Vec<i64> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match entry.event {
+                Event::Input(word) => Some(word),
+                Event::Output(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        for entry in self.entries.iter() {
+            writeln!(out, "{}", entry)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: BufRead>(input: R) -> io::Result<Transcript> {
+        let mut lines = input.lines();
+        let header = lines.next().transpose()?;
+        if header.as_deref().map(str::trim) != Some(HEADER) {
+            return Err(invalid(1, format!("expected {:?}", HEADER)));
+        }
+        let mut transcript = Transcript::new();
+        for (n, line) in lines.enumerate() {
+            let n = n + 2;
+            let line = line?;
+            let entry = match line.split_whitespace().collect::<Vec<&str>>()[..] {
+                [] => continue,
+                [cycle, kind, word] => parse_entry(cycle, kind, word),
+                _ => None,
+            };
+            let entry = entry.ok_or_else(|| invalid(n, format!("bad entry {:?}", line.trim())))?;
+            transcript.entries.push(entry);
+        }
+        Ok(transcript)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write(io::BufWriter::new(std::fs::File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Transcript> {
+        Transcript::read(io::BufReader::new(std::fs::File::open(path)?))
+    }
+
+    /// Run |program| on the recorded input, checking each event against
+    /// the transcript and stopping at the first that differs. Once the
+    /// whole transcript has matched, returns the machine as it stopped:
+    /// halted or waiting for more input.
+    pub fn replay(&self, program: Vec<i64>) -> Result<Cpu<VecDeque<i64>, Vec<i64>>, ReplayError> {
+        let seen = Arc::new(Mutex::new(Transcript::new()));
+        let mut cpu = Cpu::new(program)
+            .with_input(VecDeque::from(self.inputs()))
+            .with_output(Vec::new())
+            .with_tracer(seen.clone());
+        let mut checked = 0;
+        loop {
+            let status = cpu.resume().map_err(ReplayError::Fault)?;
+            let seen = seen.lock().unwrap();
+            for (index, &actual) in seen.entries.iter().enumerate().skip(checked) {
+                let expected = self.entries.get(index).copied();
+                if expected != Some(actual) {
+                    return Err(ReplayError::Diverged {
+                        index,
+                        expected,
+                        actual: Some(actual),
+                    });
+                }
+            }
+            checked = seen.entries.len();
+            match status {
+                Status::Output(word) => cpu.output.push(word),
+                Status::Running => unreachable!(),
+                _ if checked < self.entries.len() => {
+                    return Err(ReplayError::Diverged {
+                        index: checked,
+                        expected: Some(self.entries[checked]),
+                        actual: None,
+                    })
+                }
+                // A session which ended with the player quitting leaves the
+                // program waiting for input, just as it was recorded.
+                Status::Halted | Status::NeedsInput => return Ok(cpu),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{doubler, Shared};
+
+    // The transcript of running the doubler on |input|.
+    fn session(input: Vec<i64>) -> Vec<u8> {
+        let text = Shared::default();
+        Cpu::new(doubler())
+            .with_input(VecDeque::from(input))
+            .with_output(Vec::new())
+            .with_tracer(Recorder::new(text.clone()).unwrap())
+            .run()
+            .unwrap();
+        text.contents()
+    }
+
+    #[test]
+    fn record() {
+        let text = String::from_utf8(session(vec![21, 4, 0])).unwrap();
+        assert_eq!(
+            text,
+            "intcode transcript 1\n0 in 21\n3 out 42\n5 in 4\n8 out 8\n10 in 0\n"
+        );
+        let transcript = Transcript::read(text.as_bytes()).unwrap();
+        assert_eq!(transcript.inputs(), vec![21, 4, 0]);
+        assert_eq!(
+            transcript.entries[1],
+            Entry {
+                cycle: 3,
+                event: Event::Output(42)
+            }
+        );
+        let mut again = Vec::new();
+        transcript.write(&mut again).unwrap();
+        assert_eq!(String::from_utf8(again).unwrap(), text);
+    }
+
+    #[test]
+    fn replay() {
+        let transcript = Transcript::read(&session(vec![21, 4, 0])[..]).unwrap();
+        let cpu = transcript.replay(doubler()).unwrap();
+        assert_eq!(cpu.output, vec![42, 8]);
+
+        // A session the player quit part way leaves the program waiting
+        let quit = Transcript {
+            entries: transcript.entries[..2].to_vec(),
+        };
+        let cpu = quit.replay(doubler()).unwrap();
+        assert_eq!((cpu.ip(), cpu.output.clone()), (0, vec![42]));
+
+        // Doubling the wrong word changes the first output
+        let mut broken = doubler();
+        broken[6] = 7;
+        let err = transcript.replay(broken).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "diverged at entry 1: expected 3 out 42, got 3 out 4"
+        );
+
+        // A program that stops early leaves the rest of the transcript
+        let mut short = doubler();
+        short[5] = 99;
+        assert_eq!(
+            transcript.replay(short).err(),
+            Some(ReplayError::Diverged {
+                index: 1,
+                expected: Some(transcript.entries[1]),
+                actual: None,
+            })
+        );
+    }
+
+    #[test]
+    fn bad_input() {
+        let err = Transcript::read(&b"intcode snapshot 1\n"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = Transcript::read(&b"intcode transcript 1\n\n3 put 4\n"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 3: bad entry \"3 put 4\"");
+    }
+}